@@ -0,0 +1,291 @@
+//acpi.rs
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{fmt, slice, str};
+use x86_64::PhysAddr;
+
+use crate::apic::ApicConfig;
+use crate::memory;
+use crate::serial;
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+
+pub use fadt::{Fadt, GenericAddress};
+pub use hpet::Hpet;
+pub use madt::Madt;
+
+static RSDP_SIGNATURE : &[u8; 8] = b"RSD PTR ";
+static RSDP_V1_LENGTH : usize = 20;
+static RSDP_V2_LENGTH : usize = 36;
+
+//Real mode pointer to the Extended BIOS Data Area, as a segment.
+static EBDA_POINTER  : u64 = 0x40E;
+static EBDA_SEARCH   : usize = 1024;
+static BIOS_START    : u64 = 0xE0000;
+static BIOS_END      : u64 = 0x100000;
+
+pub static SDT_HEADER_LENGTH : usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    NoRsdp,
+    BadChecksum([u8; 4]),
+    Truncated([u8; 4])
+}
+
+/// A system description table as it sits in memory, header included.
+#[derive(Clone, Copy)]
+pub struct Sdt {
+    pub address : u64,
+    bytes       : &'static [u8]
+}
+
+impl Sdt {
+    /// Maps the table at `address` and checks its length and checksum.
+    unsafe fn load(address : u64) -> Result<Sdt, AcpiError> {
+        let header = physical_bytes(address, SDT_HEADER_LENGTH);
+        let mut signature = [0; 4];
+        signature.copy_from_slice(&header[0..4]);
+
+        let length = u32_at(header, 4) as usize;
+        if length < SDT_HEADER_LENGTH {
+            return Err(AcpiError::Truncated(signature));
+        }
+        let bytes = physical_bytes(address, length);
+        if checksum(bytes) != 0 {
+            return Err(AcpiError::BadChecksum(signature));
+        }
+        Ok(Sdt { address : address, bytes : bytes })
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        let mut signature = [0; 4];
+        signature.copy_from_slice(&self.bytes[0..4]);
+        signature
+    }
+
+    pub fn length(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.bytes[8]
+    }
+
+    pub fn oem_id(&self) -> &'static str {
+        ascii(&self.bytes[10..16])
+    }
+
+    pub fn oem_table_id(&self) -> &'static str {
+        ascii(&self.bytes[16..24])
+    }
+
+    /// Everything after the header.
+    pub fn data(&self) -> &'static [u8] {
+        &self.bytes[SDT_HEADER_LENGTH..]
+    }
+
+    /// The whole table, header included, so offsets match the spec.
+    pub fn bytes(&self) -> &'static [u8] {
+        self.bytes
+    }
+}
+
+impl fmt::Display for Sdt {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {:#x}, {} bytes, rev {}, {} {}",
+            ascii(&self.bytes[0..4]), self.address, self.length(), self.revision(),
+            self.oem_id(), self.oem_table_id())
+    }
+}
+
+pub struct Acpi {
+    pub revision : u8,
+    pub oem_id   : &'static str,
+    pub tables   : Vec<Sdt>,
+    pub madt     : Option<Madt>,
+    pub fadt     : Option<Fadt>,
+    pub hpet     : Option<Hpet>
+}
+
+impl Acpi {
+    pub fn find(&self, signature : &[u8; 4]) -> Option<Sdt> {
+        self.tables.iter().find(|t| &t.signature() == signature).copied()
+    }
+}
+
+static ACPI : OnceCell<Acpi> = OnceCell::uninit();
+
+/// Finds and parses the ACPI tables, dumping what it found over serial.
+/// Must run after `memory::init`.
+pub fn init() {
+    serial::print!("Reading ACPI Tables...");
+    match unsafe { discover() } {
+        Ok(acpi) => {
+            serial::println!("[OK] revision {}, {}", acpi.revision, acpi.oem_id);
+            dump(&acpi);
+            ACPI.init_once(|| acpi);
+        }
+        Err(error) => serial::println!("[FAILED] {:?}", error)
+    }
+}
+
+/// The parsed tables, `None` if the firmware has no ACPI.
+pub fn tables() -> Option<&'static Acpi> {
+    ACPI.get()
+}
+
+/// The Differentiated System Description Table, which holds the AML the
+/// FADT points at.
+pub fn dsdt() -> Option<Sdt> {
+    let dsdt = tables()?.fadt.as_ref()?.dsdt;
+    if dsdt == 0 {
+        return None;
+    }
+    unsafe { Sdt::load(dsdt).ok() }
+}
+
+/// APIC layout from the MADT, if there is one.
+pub fn apic_config() -> Option<ApicConfig> {
+    tables()?.madt.as_ref().map(|madt| madt.apic_config())
+}
+
+unsafe fn discover() -> Result<Acpi, AcpiError> {
+    let rsdp = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+    let bytes = physical_bytes(rsdp, RSDP_V1_LENGTH);
+    let revision = bytes[15];
+    let oem_id = ascii(&bytes[9..15]);
+
+    //ACPI 2.0+ points at the XSDT, with 64 bit entries, and checksums the
+    //extended structure separately.
+    let (root, entry_size) = if revision >= 2 {
+        let extended = physical_bytes(rsdp, RSDP_V2_LENGTH);
+        if checksum(extended) != 0 {
+            return Err(AcpiError::BadChecksum(*b"RSDP"));
+        }
+        (u64_at(extended, 24), 8)
+    } else {
+        (u32_at(bytes, 16) as u64, 4)
+    };
+
+    let root = Sdt::load(root)?;
+    let mut tables = Vec::new();
+    for entry in root.data().chunks_exact(entry_size) {
+        let address = if entry_size == 8 { u64_at(entry, 0) } else { u32_at(entry, 0) as u64 };
+        match Sdt::load(address) {
+            Ok(table) => tables.push(table),
+            Err(error) => serial::print!("[skipped {:?}]", error)
+        }
+    }
+
+    let mut acpi = Acpi {
+        revision : revision,
+        oem_id   : oem_id,
+        tables   : tables,
+        madt     : None,
+        fadt     : None,
+        hpet     : None
+    };
+    acpi.madt = acpi.find(b"APIC").map(Madt::parse);
+    acpi.fadt = acpi.find(b"FACP").map(Fadt::parse);
+    acpi.hpet = acpi.find(b"HPET").map(Hpet::parse);
+    Ok(acpi)
+}
+
+/// Looks for the RSDP in the first KiB of the EBDA, then the BIOS area.
+unsafe fn find_rsdp() -> Option<u64> {
+    let ebda = (*(memory::phys_to_virt(PhysAddr::new(EBDA_POINTER)).as_ptr::<u16>()) as u64) << 4;
+    if ebda != 0 {
+        if let Some(rsdp) = scan_for_rsdp(ebda, ebda + EBDA_SEARCH as u64) {
+            return Some(rsdp);
+        }
+    }
+    scan_for_rsdp(BIOS_START, BIOS_END)
+}
+
+unsafe fn scan_for_rsdp(start : u64, end : u64) -> Option<u64> {
+    //The RSDP is always 16 byte aligned.
+    (start..end).step_by(16)
+        .filter(|&address| address + RSDP_V1_LENGTH as u64 <= end)
+        .find(|&address| {
+            let bytes = physical_bytes(address, RSDP_V1_LENGTH);
+            &bytes[0..8] == RSDP_SIGNATURE && checksum(bytes) == 0
+        })
+}
+
+fn dump(acpi : &Acpi) {
+    for table in acpi.tables.iter() {
+        serial::println!("  {}", table);
+    }
+    if let Some(madt) = &acpi.madt {
+        serial::println!("  MADT: local APIC at {:#x}, {} processors, {} I/O APICs, {} overrides",
+            madt.local_apic_address, madt.processors.len(), madt.io_apics.len(), madt.overrides.len());
+        for io_apic in madt.io_apics.iter() {
+            serial::println!("    I/O APIC {} at {:#x}, GSI base {}",
+                io_apic.id, io_apic.address, io_apic.gsi_base);
+        }
+        for o in madt.overrides.iter() {
+            serial::println!("    IRQ {} -> GSI {}{}{}", o.irq, o.gsi,
+                if o.active_low { ", active low" } else { "" },
+                if o.level_triggered { ", level" } else { "" });
+        }
+    }
+    if let Some(fadt) = &acpi.fadt {
+        serial::println!("  FADT: SCI {}, DSDT at {:#x}, PM1a control {:#x}, reset {:?}",
+            fadt.sci_interrupt, fadt.dsdt, fadt.pm1a_control_block, fadt.reset_register);
+    }
+    if let Some(hpet) = &acpi.hpet {
+        serial::println!("  HPET: {} at {:#x}, {} comparators, min tick {}",
+            hpet.number, hpet.base_address.address, hpet.comparators(), hpet.minimum_tick);
+    }
+}
+
+/// Physical memory through the bootloader's mapping of all of it.
+unsafe fn physical_bytes(address : u64, length : usize) -> &'static [u8] {
+    let virt = memory::phys_to_virt(PhysAddr::new(address));
+    slice::from_raw_parts(virt.as_ptr::<u8>(), length)
+}
+
+fn checksum(bytes : &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn ascii(bytes : &'static [u8]) -> &'static str {
+    str::from_utf8(bytes).unwrap_or("?").trim_end()
+}
+
+//Fields are little endian and often unaligned. Reads past the end of a
+//table, e.g. fields newer than its revision, come back as 0.
+pub(crate) fn u8_at(bytes : &[u8], offset : usize) -> u8 {
+    bytes.get(offset).copied().unwrap_or(0)
+}
+
+pub(crate) fn u16_at(bytes : &[u8], offset : usize) -> u16 {
+    u8_at(bytes, offset) as u16 | (u8_at(bytes, offset + 1) as u16) << 8
+}
+
+pub(crate) fn u32_at(bytes : &[u8], offset : usize) -> u32 {
+    u16_at(bytes, offset) as u32 | (u16_at(bytes, offset + 2) as u32) << 16
+}
+
+pub(crate) fn u64_at(bytes : &[u8], offset : usize) -> u64 {
+    u32_at(bytes, offset) as u64 | (u32_at(bytes, offset + 4) as u64) << 32
+}
+
+#[test_case]
+fn test_checksum() {
+    assert_eq!(checksum(&[0x10, 0xF0]), 0);
+    assert_eq!(checksum(&[0xFF, 0x02]), 1);
+}
+
+#[test_case]
+fn test_field_reads() {
+    let bytes = [0x78, 0x56, 0x34, 0x12, 0xEF, 0xCD, 0xAB, 0x90];
+    assert_eq!(u16_at(&bytes, 0), 0x5678);
+    assert_eq!(u32_at(&bytes, 0), 0x1234_5678);
+    assert_eq!(u64_at(&bytes, 0), 0x90AB_CDEF_1234_5678);
+    assert_eq!(u32_at(&bytes, 6), 0x90AB);
+}
@@ -0,0 +1,122 @@
+//acpi/fadt.rs
+
+use super::{u8_at, u16_at, u32_at, u64_at, Sdt};
+
+//FADT flags
+static RESET_REG_SUPPORTED : u32 = 1 << 10;
+static HW_REDUCED_ACPI     : u32 = 1 << 20;
+
+//Boot architecture flags
+static BOOT_8042 : u16 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    Other(u8)
+}
+
+/// Register location as ACPI describes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub space      : AddressSpace,
+    pub bit_width  : u8,
+    pub bit_offset : u8,
+    pub access     : u8,
+    pub address    : u64
+}
+
+impl GenericAddress {
+    pub(crate) fn parse(bytes : &[u8], offset : usize) -> GenericAddress {
+        GenericAddress {
+            space      : match u8_at(bytes, offset) {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfig,
+                n => AddressSpace::Other(n)
+            },
+            bit_width  : u8_at(bytes, offset + 1),
+            bit_offset : u8_at(bytes, offset + 2),
+            access     : u8_at(bytes, offset + 3),
+            address    : u64_at(bytes, offset + 4)
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.address == 0
+    }
+}
+
+/// Fixed ACPI Description Table, signature `FACP`. Fields the table is too
+/// old to have are 0.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub revision           : u8,
+    pub firmware_ctrl      : u64,
+    pub dsdt               : u64,
+    pub sci_interrupt      : u16,
+    pub smi_command_port   : u32,
+    pub acpi_enable        : u8,
+    pub acpi_disable       : u8,
+    pub pm1a_event_block   : u32,
+    pub pm1b_event_block   : u32,
+    pub pm1a_control_block : u32,
+    pub pm1b_control_block : u32,
+    pub pm_timer_block     : u32,
+    pub pm1_event_length   : u8,
+    pub pm1_control_length : u8,
+    pub century_register   : u8,
+    pub boot_flags         : u16,
+    pub flags              : u32,
+    /// Only set if the firmware says the reset register is supported.
+    pub reset_register     : Option<GenericAddress>,
+    pub reset_value        : u8
+}
+
+impl Fadt {
+    pub fn parse(table : Sdt) -> Fadt {
+        let bytes = table.bytes();
+        let flags = u32_at(bytes, 112);
+
+        //The 64 bit pointers supersede the 32 bit ones when present.
+        let x_firmware_ctrl = u64_at(bytes, 132);
+        let x_dsdt = u64_at(bytes, 140);
+
+        let reset_register = GenericAddress::parse(bytes, 116);
+        Fadt {
+            revision           : table.revision(),
+            firmware_ctrl      : if x_firmware_ctrl != 0 { x_firmware_ctrl } else { u32_at(bytes, 36) as u64 },
+            dsdt               : if x_dsdt != 0 { x_dsdt } else { u32_at(bytes, 40) as u64 },
+            sci_interrupt      : u16_at(bytes, 46),
+            smi_command_port   : u32_at(bytes, 48),
+            acpi_enable        : u8_at(bytes, 52),
+            acpi_disable       : u8_at(bytes, 53),
+            pm1a_event_block   : u32_at(bytes, 56),
+            pm1b_event_block   : u32_at(bytes, 60),
+            pm1a_control_block : u32_at(bytes, 64),
+            pm1b_control_block : u32_at(bytes, 68),
+            pm_timer_block     : u32_at(bytes, 76),
+            pm1_event_length   : u8_at(bytes, 88),
+            pm1_control_length : u8_at(bytes, 89),
+            century_register   : u8_at(bytes, 108),
+            boot_flags         : u16_at(bytes, 109),
+            flags              : flags,
+            reset_register     : if flags & RESET_REG_SUPPORTED != 0 && !reset_register.is_null() {
+                Some(reset_register)
+            } else {
+                None
+            },
+            reset_value        : u8_at(bytes, 128)
+        }
+    }
+
+    pub fn has_8042(&self) -> bool {
+        //Revision 1 tables predate the flag, assume the usual PC.
+        self.revision < 2 || self.boot_flags & BOOT_8042 != 0
+    }
+
+    pub fn is_hardware_reduced(&self) -> bool {
+        self.flags & HW_REDUCED_ACPI != 0
+    }
+}
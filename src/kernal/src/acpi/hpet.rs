@@ -0,0 +1,40 @@
+//acpi/hpet.rs
+
+use super::{u8_at, u16_at, u32_at, GenericAddress, Sdt};
+
+/// High Precision Event Timer description, signature `HPET`.
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub hardware_revision : u8,
+    pub comparator_count  : u8,
+    pub counter_64_bit    : bool,
+    pub legacy_capable    : bool,
+    pub vendor_id         : u16,
+    pub base_address      : GenericAddress,
+    pub number            : u8,
+    pub minimum_tick      : u16,
+    pub page_protection   : u8
+}
+
+impl Hpet {
+    pub fn parse(table : Sdt) -> Hpet {
+        let bytes = table.bytes();
+        let block_id = u32_at(bytes, 36);
+        Hpet {
+            hardware_revision : block_id as u8,
+            comparator_count  : ((block_id >> 8) & 0x1F) as u8,
+            counter_64_bit    : block_id & (1 << 13) != 0,
+            legacy_capable    : block_id & (1 << 15) != 0,
+            vendor_id         : (block_id >> 16) as u16,
+            base_address      : GenericAddress::parse(bytes, 40),
+            number            : u8_at(bytes, 52),
+            minimum_tick      : u16_at(bytes, 53),
+            page_protection   : u8_at(bytes, 55)
+        }
+    }
+
+    /// The field stores the index of the last comparator.
+    pub fn comparators(&self) -> u8 {
+        self.comparator_count + 1
+    }
+}
@@ -0,0 +1,115 @@
+//acpi/madt.rs
+
+use alloc::vec::Vec;
+
+use super::{u8_at, u16_at, u32_at, u64_at, Sdt};
+use crate::apic::{ApicConfig, IoApicInfo, SourceOverride};
+
+static ENTRY_LOCAL_APIC          : u8 = 0;
+static ENTRY_IO_APIC             : u8 = 1;
+static ENTRY_SOURCE_OVERRIDE     : u8 = 2;
+static ENTRY_LOCAL_APIC_NMI      : u8 = 4;
+static ENTRY_LOCAL_APIC_OVERRIDE : u8 = 5;
+
+static LOCAL_APIC_ENABLED        : u32 = 1 << 0;
+static LOCAL_APIC_ONLINE_CAPABLE : u32 = 1 << 1;
+
+//MPS INTI flags
+static POLARITY_MASK    : u16 = 0b0011;
+static POLARITY_LOW     : u16 = 0b0011;
+static TRIGGER_MASK     : u16 = 0b1100;
+static TRIGGER_LEVEL    : u16 = 0b1100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_id : u8,
+    pub apic_id      : u8,
+    pub usable       : bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// 0xFF for every processor.
+    pub processor_id : u8,
+    pub lint         : u8,
+    pub flags        : u16
+}
+
+/// Multiple APIC Description Table, signature `APIC`.
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address : u64,
+    /// The system also has 8259 PICs that need masking.
+    pub pcat_compatible    : bool,
+    pub processors         : Vec<Processor>,
+    pub io_apics           : Vec<IoApicInfo>,
+    pub overrides          : Vec<SourceOverride>,
+    pub nmis               : Vec<LocalApicNmi>
+}
+
+impl Madt {
+    pub fn parse(table : Sdt) -> Madt {
+        let bytes = table.bytes();
+        let mut madt = Madt {
+            local_apic_address : u32_at(bytes, 36) as u64,
+            pcat_compatible    : u32_at(bytes, 40) & 1 != 0,
+            processors         : Vec::new(),
+            io_apics           : Vec::new(),
+            overrides          : Vec::new(),
+            nmis               : Vec::new()
+        };
+
+        let mut offset = 44;
+        while offset + 2 <= bytes.len() {
+            let kind = bytes[offset];
+            let length = bytes[offset + 1] as usize;
+            if length < 2 || offset + length > bytes.len() {
+                break;
+            }
+            let entry = &bytes[offset..offset + length];
+
+            if kind == ENTRY_LOCAL_APIC {
+                let flags = u32_at(entry, 4);
+                madt.processors.push(Processor {
+                    processor_id : u8_at(entry, 2),
+                    apic_id      : u8_at(entry, 3),
+                    usable       : flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0
+                });
+            } else if kind == ENTRY_IO_APIC {
+                madt.io_apics.push(IoApicInfo {
+                    id       : u8_at(entry, 2),
+                    address  : u32_at(entry, 4) as u64,
+                    gsi_base : u32_at(entry, 8)
+                });
+            } else if kind == ENTRY_SOURCE_OVERRIDE {
+                //ISA bus defaults are active high, edge triggered.
+                let flags = u16_at(entry, 8);
+                madt.overrides.push(SourceOverride {
+                    irq             : u8_at(entry, 3),
+                    gsi             : u32_at(entry, 4),
+                    active_low      : flags & POLARITY_MASK == POLARITY_LOW,
+                    level_triggered : flags & TRIGGER_MASK == TRIGGER_LEVEL
+                });
+            } else if kind == ENTRY_LOCAL_APIC_NMI {
+                madt.nmis.push(LocalApicNmi {
+                    processor_id : u8_at(entry, 2),
+                    flags        : u16_at(entry, 3),
+                    lint         : u8_at(entry, 5)
+                });
+            } else if kind == ENTRY_LOCAL_APIC_OVERRIDE {
+                madt.local_apic_address = u64_at(entry, 4);
+            }
+
+            offset += length;
+        }
+        madt
+    }
+
+    pub fn apic_config(&self) -> ApicConfig {
+        ApicConfig {
+            local_apic_address : Some(self.local_apic_address),
+            io_apics           : self.io_apics.clone(),
+            overrides          : self.overrides.clone()
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//allocator.rs
+
+use alloc::alloc::Layout;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::mapper::MapToError;
+
+use crate::memory;
+use crate::serial;
+
+pub mod bump;
+pub mod linked_list;
+pub mod fixed_size_block;
+
+pub const HEAP_START : usize = 0x_4444_4444_0000;
+pub const HEAP_SIZE  : usize = 1024 * 1024;
+
+#[cfg(any(
+    all(feature = "bump", feature = "linked-list"),
+    all(feature = "bump", feature = "fixed-size-block"),
+    all(feature = "linked-list", feature = "fixed-size-block")
+))]
+compile_error!("Only one of the `bump`, `linked-list` and `fixed-size-block` features may be enabled");
+
+#[cfg(not(any(feature = "bump", feature = "linked-list", feature = "fixed-size-block")))]
+compile_error!("One of the `bump`, `linked-list` or `fixed-size-block` features must be enabled");
+
+#[cfg(feature = "bump")]
+#[global_allocator]
+static ALLOCATOR : Locked<bump::BumpAllocator> =
+    Locked::new(bump::BumpAllocator::new());
+
+#[cfg(feature = "linked-list")]
+#[global_allocator]
+static ALLOCATOR : Locked<linked_list::LinkedListAllocator> =
+    Locked::new(linked_list::LinkedListAllocator::new());
+
+#[cfg(feature = "fixed-size-block")]
+#[global_allocator]
+static ALLOCATOR : Locked<fixed_size_block::FixedSizeBlockAllocator> =
+    Locked::new(fixed_size_block::FixedSizeBlockAllocator::new());
+
+/// Common interface of the heap allocator designs, so they can be swapped
+/// with a cargo feature and compared through the same statistics.
+pub trait HeapAllocator {
+    /// Unsafe because the caller must guarantee the region is mapped, unused
+    /// and that this is only called once.
+    unsafe fn init(&mut self, heap_start : usize, heap_size : usize);
+
+    fn name(&self) -> &'static str;
+
+    fn stats(&self) -> HeapStats;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapStats {
+    pub heap_size           : usize,
+    pub used                : usize,
+    pub free                : usize,
+    pub allocations         : usize,
+    pub total_allocations   : usize,
+    pub failed_allocations  : usize,
+    pub free_regions        : usize,
+    pub largest_free_region : usize
+}
+
+impl HeapStats {
+    /// Percentage of free memory that is not part of the largest free region,
+    /// 0 meaning all free memory is contiguous.
+    pub fn fragmentation(&self) -> usize {
+        if self.free == 0 {
+            return 0;
+        }
+        100 - (self.largest_free_region * 100) / self.free
+    }
+}
+
+/// `GlobalAlloc` only hands out `&self`, so the allocators live behind a spinlock.
+pub struct Locked<A> {
+    inner : spin::Mutex<A>
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner : A) -> Self {
+        Locked { inner : spin::Mutex::new(inner) }
+    }
+
+    pub fn lock(&self) -> spin::MutexGuard<A> {
+        self.inner.lock()
+    }
+}
+
+/// Maps `HEAP_SIZE` bytes of fresh frames at `HEAP_START` and hands them to
+/// the global allocator. Must run after `memory::init`.
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+    serial::print!("Initialising Heap...");
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    let heap_end = heap_start + HEAP_SIZE - 1u64;
+    let pages = Page::range_inclusive(
+        Page::containing_address(heap_start),
+        Page::containing_address(heap_end)
+    );
+
+    memory::with_mapper(|mapper| {
+        memory::with_frame_allocator(|frames| {
+            for page in pages {
+                let frame = frames.allocate().ok_or(MapToError::FrameAllocationFailed)?;
+                let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+                unsafe {
+                    mapper.map_to(page, frame, flags, frames)?.flush();
+                }
+            }
+            Ok(())
+        })
+    })?;
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    }
+    serial::println!("[OK]");
+    Ok(())
+}
+
+pub fn name() -> &'static str {
+    ALLOCATOR.lock().name()
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+pub fn used() -> usize {
+    stats().used
+}
+
+pub fn free() -> usize {
+    stats().free
+}
+
+/// Align `addr` upwards to `align`, which must be a power of two.
+pub fn align_up(addr : usize, align : usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout : Layout) -> ! {
+    serial::println!("Allocation Failed: {:?}", layout);
+    panic!("Allocation Failed: {:?}", layout);
+}
+
+#[test_case]
+fn test_align_up() {
+    assert_eq!(align_up(0, 8), 0);
+    assert_eq!(align_up(1, 8), 8);
+    assert_eq!(align_up(8, 8), 8);
+    assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+}
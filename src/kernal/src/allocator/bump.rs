@@ -0,0 +1,89 @@
+//allocator/bump.rs
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use super::{align_up, HeapAllocator, HeapStats, Locked};
+
+/// Hands out memory by bumping a pointer, only reclaiming it once every
+/// allocation has been freed. Fastest possible allocator, used as a baseline
+/// for benchmarks.
+pub struct BumpAllocator {
+    heap_start         : usize,
+    heap_end           : usize,
+    next               : usize,
+    allocations        : usize,
+    total_allocations  : usize,
+    failed_allocations : usize
+}
+
+impl BumpAllocator {
+    pub const fn new() -> Self {
+        BumpAllocator {
+            heap_start         : 0,
+            heap_end           : 0,
+            next               : 0,
+            allocations        : 0,
+            total_allocations  : 0,
+            failed_allocations : 0
+        }
+    }
+}
+
+impl HeapAllocator for BumpAllocator {
+    unsafe fn init(&mut self, heap_start : usize, heap_size : usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    fn name(&self) -> &'static str {
+        "bump"
+    }
+
+    fn stats(&self) -> HeapStats {
+        let heap_size = self.heap_end - self.heap_start;
+        let free = self.heap_end - self.next;
+        HeapStats {
+            heap_size           : heap_size,
+            used                : heap_size - free,
+            free                : free,
+            allocations         : self.allocations,
+            total_allocations   : self.total_allocations,
+            failed_allocations  : self.failed_allocations,
+            free_regions        : if free > 0 { 1 } else { 0 },
+            largest_free_region : free
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BumpAllocator> {
+    unsafe fn alloc(&self, layout : Layout) -> *mut u8 {
+        let mut bump = self.lock();
+
+        let alloc_start = align_up(bump.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => { bump.failed_allocations += 1; return ptr::null_mut(); }
+        };
+
+        if alloc_end > bump.heap_end {
+            bump.failed_allocations += 1;
+            ptr::null_mut()
+        } else {
+            bump.next = alloc_end;
+            bump.allocations += 1;
+            bump.total_allocations += 1;
+            alloc_start as *mut u8
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr : *mut u8, _layout : Layout) {
+        let mut bump = self.lock();
+
+        bump.allocations -= 1;
+        if bump.allocations == 0 {
+            bump.next = bump.heap_start;
+        }
+    }
+}
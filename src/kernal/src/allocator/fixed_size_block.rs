@@ -0,0 +1,143 @@
+//allocator/fixed_size_block.rs
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr};
+
+use super::linked_list::LinkedListAllocator;
+use super::{HeapAllocator, HeapStats, Locked};
+
+/// Block sizes are powers of two, so they double as the block alignment.
+const BLOCK_SIZES : &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct BlockNode {
+    next : Option<&'static mut BlockNode>
+}
+
+/// Serves small allocations from per-size free lists of fixed blocks and
+/// everything bigger than the largest block size from a linked-list fallback.
+/// Freed blocks go back on their list rather than to the fallback.
+pub struct FixedSizeBlockAllocator {
+    list_heads         : [Option<&'static mut BlockNode>; BLOCK_SIZES.len()],
+    fallback           : LinkedListAllocator,
+    allocations        : usize,
+    total_allocations  : usize,
+    failed_allocations : usize
+}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        const EMPTY : Option<&'static mut BlockNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads         : [EMPTY; BLOCK_SIZES.len()],
+            fallback           : LinkedListAllocator::new(),
+            allocations        : 0,
+            total_allocations  : 0,
+            failed_allocations : 0
+        }
+    }
+
+    pub unsafe fn allocate(&mut self, layout : Layout) -> *mut u8 {
+        let ptr = match list_index(&layout) {
+            Some(index) => {
+                match self.list_heads[index].take() {
+                    Some(node) => {
+                        self.list_heads[index] = node.next.take();
+                        node as *mut BlockNode as *mut u8
+                    }
+                    None => {
+                        let block_size = BLOCK_SIZES[index];
+                        let block_layout = Layout::from_size_align(block_size, block_size).unwrap();
+                        self.fallback.allocate(block_layout)
+                    }
+                }
+            }
+            None => self.fallback.allocate(layout)
+        };
+
+        if ptr.is_null() {
+            self.failed_allocations += 1;
+        } else {
+            self.allocations += 1;
+            self.total_allocations += 1;
+        }
+        ptr
+    }
+
+    pub unsafe fn deallocate(&mut self, ptr : *mut u8, layout : Layout) {
+        match list_index(&layout) {
+            Some(index) => {
+                assert!(mem::size_of::<BlockNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<BlockNode>() <= BLOCK_SIZES[index]);
+
+                let new_node = BlockNode { next : self.list_heads[index].take() };
+                let new_node_ptr = ptr as *mut BlockNode;
+                new_node_ptr.write(new_node);
+                self.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => self.fallback.deallocate(ptr, layout)
+        }
+        self.allocations -= 1;
+    }
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    unsafe fn init(&mut self, heap_start : usize, heap_size : usize) {
+        self.fallback.init(heap_start, heap_size);
+    }
+
+    fn name(&self) -> &'static str {
+        "fixed-size-block"
+    }
+
+    fn stats(&self) -> HeapStats {
+        let fallback = self.fallback.stats();
+
+        //Blocks sitting on the free lists are still allocated as far as the
+        //fallback is concerned, but they are free memory to our callers.
+        let mut cached_blocks = 0;
+        let mut cached_bytes = 0;
+        let mut largest_free_region = fallback.largest_free_region;
+        for (index, head) in self.list_heads.iter().enumerate() {
+            let block_size = BLOCK_SIZES[index];
+            let footprint = LinkedListAllocator::footprint(
+                Layout::from_size_align(block_size, block_size).unwrap()
+            );
+
+            let mut current = head;
+            while let Some(block) = current {
+                cached_blocks += 1;
+                cached_bytes += footprint;
+                largest_free_region = largest_free_region.max(BLOCK_SIZES[index]);
+                current = &block.next;
+            }
+        }
+
+        HeapStats {
+            heap_size           : fallback.heap_size,
+            used                : fallback.used - cached_bytes,
+            free                : fallback.free + cached_bytes,
+            allocations         : self.allocations,
+            total_allocations   : self.total_allocations,
+            failed_allocations  : self.failed_allocations,
+            free_regions        : fallback.free_regions + cached_blocks,
+            largest_free_region : largest_free_region
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout : Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr : *mut u8, layout : Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
+
+/// Index of the smallest block size that fits the layout, or `None` if the
+/// allocation has to go to the fallback allocator.
+fn list_index(layout : &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
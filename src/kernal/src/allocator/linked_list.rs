@@ -0,0 +1,217 @@
+//allocator/linked_list.rs
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr};
+
+use super::{align_up, HeapAllocator, HeapStats, Locked};
+
+struct ListNode {
+    size : usize,
+    next : Option<&'static mut ListNode>
+}
+
+impl ListNode {
+    const fn new(size : usize) -> Self {
+        ListNode { size : size, next : None }
+    }
+
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+/// First-fit allocator over an address-ordered free list. Freed regions are
+/// merged with their neighbours so the heap does not splinter over time.
+pub struct LinkedListAllocator {
+    head               : ListNode,
+    heap_size          : usize,
+    used               : usize,
+    allocations        : usize,
+    total_allocations  : usize,
+    failed_allocations : usize
+}
+
+impl LinkedListAllocator {
+    pub const fn new() -> Self {
+        LinkedListAllocator {
+            head               : ListNode::new(0),
+            heap_size          : 0,
+            used               : 0,
+            allocations        : 0,
+            total_allocations  : 0,
+            failed_allocations : 0
+        }
+    }
+
+    pub unsafe fn allocate(&mut self, layout : Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        if let Some((region, alloc_start)) = self.find_region(size, align) {
+            let region_start = region.start_addr();
+            let region_end = region.end_addr();
+            let alloc_end = alloc_start + size;
+
+            if alloc_start > region_start {
+                self.add_free_region(region_start, alloc_start - region_start);
+            }
+            if region_end > alloc_end {
+                self.add_free_region(alloc_end, region_end - alloc_end);
+            }
+
+            self.used += size;
+            self.allocations += 1;
+            self.total_allocations += 1;
+            alloc_start as *mut u8
+        } else {
+            self.failed_allocations += 1;
+            ptr::null_mut()
+        }
+    }
+
+    pub unsafe fn deallocate(&mut self, ptr : *mut u8, layout : Layout) {
+        let (size, _) = Self::size_align(layout);
+
+        self.add_free_region(ptr as usize, size);
+        self.used -= size;
+        self.allocations -= 1;
+    }
+
+    /// Inserts a region into the address-ordered free list, merging it with
+    /// the regions directly before and after it where they touch.
+    unsafe fn add_free_region(&mut self, addr : usize, size : usize) {
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        let mut current = &mut self.head;
+        while current.next.as_ref().map_or(false, |next| next.start_addr() < addr) {
+            current = current.next.as_mut().unwrap();
+        }
+
+        let mut node = ListNode::new(size);
+        node.next = current.next.take();
+        let node_ptr = addr as *mut ListNode;
+        node_ptr.write(node);
+        let node = &mut *node_ptr;
+
+        if let Some(next) = node.next.take() {
+            if node.end_addr() == next.start_addr() {
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
+
+        //The head is a zero sized sentinel and is never merged into.
+        if current.size != 0 && current.end_addr() == node.start_addr() {
+            current.size += node.size;
+            current.next = node.next.take();
+        } else {
+            current.next = Some(node);
+        }
+    }
+
+    /// Unlinks and returns the first free region that fits the allocation,
+    /// together with the aligned start address inside it.
+    fn find_region(&mut self, size : usize, align : usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+
+        while let Some(ref mut region) = current.next {
+            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
+                let next = region.next.take();
+                let found = Some((current.next.take().unwrap(), alloc_start));
+                current.next = next;
+                return found;
+            } else {
+                current = current.next.as_mut().unwrap();
+            }
+        }
+
+        None
+    }
+
+    fn alloc_from_region(region : &ListNode, size : usize, align : usize) -> Result<usize, ()> {
+        let alloc_start = align_up(region.start_addr(), align);
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+
+        if alloc_end > region.end_addr() {
+            return Err(());
+        }
+
+        //Leftovers on either side have to be big enough to go back on the list.
+        let padding = alloc_start - region.start_addr();
+        if padding > 0 && padding < mem::size_of::<ListNode>() {
+            return Err(());
+        }
+
+        let excess = region.end_addr() - alloc_end;
+        if excess > 0 && excess < mem::size_of::<ListNode>() {
+            return Err(());
+        }
+
+        Ok(alloc_start)
+    }
+
+    /// Number of heap bytes an allocation of `layout` actually takes up.
+    pub fn footprint(layout : Layout) -> usize {
+        Self::size_align(layout).0
+    }
+
+    /// Rounds the layout up so every allocation can later hold a `ListNode`.
+    fn size_align(layout : Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("Adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+}
+
+impl HeapAllocator for LinkedListAllocator {
+    unsafe fn init(&mut self, heap_start : usize, heap_size : usize) {
+        self.heap_size = heap_size;
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    fn name(&self) -> &'static str {
+        "linked-list"
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut free_regions = 0;
+        let mut largest_free_region = 0;
+
+        let mut current = &self.head.next;
+        while let Some(region) = current {
+            free_regions += 1;
+            largest_free_region = largest_free_region.max(region.size);
+            current = &region.next;
+        }
+
+        HeapStats {
+            heap_size           : self.heap_size,
+            used                : self.used,
+            free                : self.heap_size - self.used,
+            allocations         : self.allocations,
+            total_allocations   : self.total_allocations,
+            failed_allocations  : self.failed_allocations,
+            free_regions        : free_regions,
+            largest_free_region : largest_free_region
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout : Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr : *mut u8, layout : Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
@@ -0,0 +1,345 @@
+//apic.rs
+
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::registers::model_specific::Msr;
+
+use crate::memory;
+use crate::pics;
+use crate::pit;
+use crate::serial;
+use crate::time;
+
+//CPUID leaf 1 EDX
+static CPUID_APIC : u32 = 1 << 9;
+
+static IA32_APIC_BASE      : u32 = 0x1B;
+static APIC_BASE_ENABLE    : u64 = 1 << 11;
+static APIC_BASE_ADDR_MASK : u64 = 0x000F_FFFF_FFFF_F000;
+
+//Local APIC registers, as offsets into its 4 KiB MMIO page.
+pub static LAPIC_ID            : usize = 0x020;
+pub static LAPIC_VERSION       : usize = 0x030;
+pub static LAPIC_TPR           : usize = 0x080;
+pub static LAPIC_EOI           : usize = 0x0B0;
+pub static LAPIC_SPURIOUS      : usize = 0x0F0;
+pub static LAPIC_LVT_TIMER     : usize = 0x320;
+pub static LAPIC_LVT_LINT0     : usize = 0x350;
+pub static LAPIC_LVT_LINT1     : usize = 0x360;
+pub static LAPIC_LVT_ERROR     : usize = 0x370;
+pub static LAPIC_TIMER_INITIAL : usize = 0x380;
+pub static LAPIC_TIMER_CURRENT : usize = 0x390;
+pub static LAPIC_TIMER_DIVIDE  : usize = 0x3E0;
+
+static SPURIOUS_ENABLE     : u32 = 1 << 8;
+static LVT_MASKED         : u32 = 1 << 16;
+static LVT_TIMER_PERIODIC : u32 = 1 << 17;
+static TIMER_DIVIDE_16    : u32 = 0b0011;
+
+pub const SPURIOUS_VECTOR : u8 = 0xFF;
+
+//I/O APIC registers, reached through the select and window registers.
+static IOAPIC_REGSEL      : usize = 0x00;
+static IOAPIC_WINDOW      : usize = 0x10;
+static IOAPIC_REG_ID      : u32 = 0x00;
+static IOAPIC_REG_VERSION : u32 = 0x01;
+static IOAPIC_REG_TABLE   : u32 = 0x10;
+
+static REDIRECT_ACTIVE_LOW : u64 = 1 << 13;
+static REDIRECT_LEVEL      : u64 = 1 << 15;
+static REDIRECT_MASKED     : u64 = 1 << 16;
+
+pub const ISA_IRQS : u8 = 16;
+
+//Where chipsets without ACPI tables put the first I/O APIC.
+pub static DEFAULT_IOAPIC_ADDRESS : u64 = 0xFEC0_0000;
+
+/// PIT cycles the APIC timer is calibrated over, about 10 ms.
+static CALIBRATION_CYCLES : u16 = 11_932;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id       : u8,
+    pub address  : u64,
+    pub gsi_base : u32
+}
+
+/// An ISA IRQ that is wired to a different global system interrupt, or with
+/// a different polarity or trigger mode, than the ISA default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceOverride {
+    pub irq             : u8,
+    pub gsi             : u32,
+    pub active_low      : bool,
+    pub level_triggered : bool
+}
+
+/// Interrupt controller layout, normally read from the firmware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApicConfig {
+    /// `None` to use the address in IA32_APIC_BASE.
+    pub local_apic_address : Option<u64>,
+    pub io_apics           : Vec<IoApicInfo>,
+    pub overrides          : Vec<SourceOverride>
+}
+
+impl ApicConfig {
+    /// The usual PC layout: one I/O APIC at its default address, with the
+    /// PIT moved to pin 2 where the cascade would be.
+    pub fn legacy() -> ApicConfig {
+        let mut io_apics = Vec::new();
+        io_apics.push(IoApicInfo { id : 0, address : DEFAULT_IOAPIC_ADDRESS, gsi_base : 0 });
+        let mut overrides = Vec::new();
+        overrides.push(SourceOverride { irq : 0, gsi : 2, active_low : false, level_triggered : false });
+        ApicConfig { local_apic_address : None, io_apics : io_apics, overrides : overrides }
+    }
+
+    /// Where `irq` is wired, `None` if its default line was taken over by
+    /// another IRQ's override, e.g. IRQ 2 once the PIT has moved there.
+    fn route(&self, irq : u8) -> Option<SourceOverride> {
+        if let Some(route) = self.overrides.iter().find(|o| o.irq == irq) {
+            return Some(*route);
+        }
+        if self.overrides.iter().any(|o| o.gsi == irq as u32) {
+            return None;
+        }
+        Some(SourceOverride { irq : irq, gsi : irq as u32, active_low : false, level_triggered : false })
+    }
+}
+
+struct IoApic {
+    base     : VirtAddr,
+    gsi_base : u32,
+    entries  : u32
+}
+
+impl IoApic {
+    fn read(&self, register : u32) -> u32 {
+        unsafe {
+            write_volatile((self.base.as_u64() as usize + IOAPIC_REGSEL) as *mut u32, register);
+            read_volatile((self.base.as_u64() as usize + IOAPIC_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(&self, register : u32, value : u32) {
+        unsafe {
+            write_volatile((self.base.as_u64() as usize + IOAPIC_REGSEL) as *mut u32, register);
+            write_volatile((self.base.as_u64() as usize + IOAPIC_WINDOW) as *mut u32, value);
+        }
+    }
+
+    fn handles(&self, gsi : u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entries
+    }
+
+    fn read_entry(&self, gsi : u32) -> u64 {
+        let register = IOAPIC_REG_TABLE + (gsi - self.gsi_base) * 2;
+        self.read(register) as u64 | (self.read(register + 1) as u64) << 32
+    }
+
+    fn write_entry(&self, gsi : u32, entry : u64) {
+        let register = IOAPIC_REG_TABLE + (gsi - self.gsi_base) * 2;
+        //Masked while the halves disagree.
+        self.write(register, (entry | REDIRECT_MASKED) as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+struct IoApics {
+    config   : ApicConfig,
+    io_apics : Vec<IoApic>
+}
+
+impl IoApics {
+    fn for_irq(&self, irq : u8) -> Option<(&IoApic, SourceOverride)> {
+        let route = self.config.route(irq)?;
+        self.io_apics.iter().find(|a| a.handles(route.gsi)).map(|a| (a, route))
+    }
+}
+
+//Virtual address of the local APIC, 0 while the PICs are in use. Kept out
+//of a lock so `end_of_interrupt` is safe from any interrupt handler.
+static LAPIC_BASE : AtomicU64 = AtomicU64::new(0);
+static TIMER_HZ   : AtomicU64 = AtomicU64::new(0);
+static IO_APICS   : Mutex<Option<IoApics>> = Mutex::new(None);
+
+pub fn is_present() -> bool {
+    unsafe { __cpuid(1).edx & CPUID_APIC != 0 }
+}
+
+/// Whether interrupts are being delivered through the APICs.
+pub fn is_enabled() -> bool {
+    LAPIC_BASE.load(Ordering::Acquire) != 0
+}
+
+fn lapic_read(register : usize) -> u32 {
+    let base = LAPIC_BASE.load(Ordering::Acquire) as usize;
+    unsafe { read_volatile((base + register) as *const u32) }
+}
+
+fn lapic_write(register : usize, value : u32) {
+    let base = LAPIC_BASE.load(Ordering::Acquire) as usize;
+    unsafe { write_volatile((base + register) as *mut u32, value) }
+}
+
+/// Switches interrupt delivery from the 8259 PICs to the local and I/O
+/// APICs, keeping the ISA IRQs on the same vectors. Leaves the PICs in
+/// charge if there is no APIC. Must run after `memory::init`.
+pub fn init(config : ApicConfig) {
+    serial::print!("Initialising APIC...");
+    if !is_present() {
+        serial::println!("[NONE] using the 8259 PICs");
+        return;
+    }
+
+    let mut base_msr = Msr::new(IA32_APIC_BASE);
+    let msr_value = unsafe { base_msr.read() };
+    let lapic_phys = config.local_apic_address.unwrap_or(msr_value & APIC_BASE_ADDR_MASK);
+
+    let lapic = match memory::map_mmio(PhysAddr::new(lapic_phys), 4096) {
+        Ok(lapic) => lapic,
+        Err(_) => {
+            serial::println!("[FAILED] could not map the local APIC, using the 8259 PICs");
+            return;
+        }
+    };
+
+    let mut io_apics = Vec::new();
+    for info in config.io_apics.iter() {
+        match memory::map_mmio(PhysAddr::new(info.address), 0x20) {
+            Ok(base) => {
+                let mut io_apic = IoApic { base : base, gsi_base : info.gsi_base, entries : 0 };
+                io_apic.entries = ((io_apic.read(IOAPIC_REG_VERSION) >> 16) & 0xFF) + 1;
+                io_apics.push(io_apic);
+            }
+            Err(_) => serial::print!("[could not map I/O APIC {}]", info.id)
+        }
+    }
+    if io_apics.is_empty() {
+        serial::println!("[FAILED] no I/O APIC, using the 8259 PICs");
+        return;
+    }
+
+    without_interrupts(|| {
+        //Carry over which lines were enabled, less the PIT, which the APIC
+        //timer replaces, and the cascade, which no longer exists.
+        let pic_mask = pic_masks() | 1 << pics::CASCADE_IRQ | 1;
+        mask_pics();
+
+        unsafe { base_msr.write(msr_value | APIC_BASE_ENABLE); }
+        LAPIC_BASE.store(lapic.as_u64(), Ordering::Release);
+
+        lapic_write(LAPIC_TPR, 0);
+        lapic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+        lapic_write(LAPIC_SPURIOUS, SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+        lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+
+        let io_apics = IoApics { config : config, io_apics : io_apics };
+        let destination = (lapic_read(LAPIC_ID) >> 24) as u64;
+        for irq in 0..ISA_IRQS {
+            if let Some((io_apic, route)) = io_apics.for_irq(irq) {
+                let mut entry = (pics::PIC_1_OFFSET + irq) as u64 | destination << 56;
+                if route.active_low { entry |= REDIRECT_ACTIVE_LOW; }
+                if route.level_triggered { entry |= REDIRECT_LEVEL; }
+                if pic_mask & (1 << irq) != 0 { entry |= REDIRECT_MASKED; }
+                io_apic.write_entry(route.gsi, entry);
+            }
+        }
+        *IO_APICS.lock() = Some(io_apics);
+    });
+
+    let timer_hz = without_interrupts(calibrate_timer);
+    TIMER_HZ.store(timer_hz, Ordering::Relaxed);
+    //Keep ticking at whatever rate the PIT was set to until `set_tick_rate`.
+    set_timer_frequency((pit::FREQUENCY as u64 / time::reload_value() as u64) as u32);
+
+    serial::println!("[OK] local APIC {} at {:#x}, timer {} kHz",
+        lapic_read(LAPIC_ID) >> 24, lapic_phys, timer_hz / 1000);
+}
+
+fn pic_masks() -> u16 {
+    unsafe {
+        let primary = Port::<u8>::new(pics::PIC_1_DATA).read() as u16;
+        let secondary = Port::<u8>::new(pics::PIC_2_DATA).read() as u16;
+        primary | secondary << 8
+    }
+}
+
+fn mask_pics() {
+    unsafe {
+        Port::<u8>::new(pics::PIC_1_DATA).write(0xFF);
+        Port::<u8>::new(pics::PIC_2_DATA).write(0xFF);
+    }
+}
+
+/// Counts how far the APIC timer gets over a PIT channel 2 countdown.
+fn calibrate_timer() -> u64 {
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    //The APIC timer is already running when the PIT gate opens, and the count
+    //is sampled straight after, so loading the PIT isn't measured.
+    lapic_write(LAPIC_TIMER_INITIAL, u32::MAX);
+    pit::start_countdown(CALIBRATION_CYCLES);
+    let start = lapic_read(LAPIC_TIMER_CURRENT);
+    while !pit::countdown_finished() {}
+    let elapsed = start - lapic_read(LAPIC_TIMER_CURRENT);
+    lapic_write(LAPIC_TIMER_INITIAL, 0);
+    pit::stop_countdown();
+
+    elapsed as u64 * pit::FREQUENCY as u64 / CALIBRATION_CYCLES as u64
+}
+
+/// Rate the APIC timer counts at after its divider, 0 if not calibrated.
+pub fn timer_frequency() -> u64 {
+    TIMER_HZ.load(Ordering::Relaxed)
+}
+
+/// Fires the timer vector `hertz` times a second from the APIC timer.
+pub fn set_timer_frequency(hertz : u32) {
+    let count = (timer_frequency() / hertz.max(1) as u64).max(1).min(u32::MAX as u64) as u32;
+    without_interrupts(|| {
+        lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        lapic_write(LAPIC_LVT_TIMER, LVT_TIMER_PERIODIC | pics::InterruptIndex::Timer.as_u8() as u32);
+        lapic_write(LAPIC_TIMER_INITIAL, count);
+    });
+}
+
+/// Signals the end of an interrupt to the local APIC.
+pub fn end_of_interrupt() {
+    lapic_write(LAPIC_EOI, 0);
+}
+
+/// Enables or disables an ISA IRQ's redirection entry.
+pub fn set_irq_masked(irq : u8, masked : bool) {
+    without_interrupts(|| {
+        if let Some(io_apics) = IO_APICS.lock().as_ref() {
+            if let Some((io_apic, route)) = io_apics.for_irq(irq) {
+                let entry = io_apic.read_entry(route.gsi);
+                let entry = if masked { entry | REDIRECT_MASKED } else { entry & !REDIRECT_MASKED };
+                io_apic.write_entry(route.gsi, entry);
+            }
+        }
+    });
+}
+
+pub fn local_apic_id() -> u8 {
+    (lapic_read(LAPIC_ID) >> 24) as u8
+}
+
+pub fn local_apic_version() -> u8 {
+    lapic_read(LAPIC_VERSION) as u8
+}
+
+pub fn io_apic_ids() -> Vec<u8> {
+    without_interrupts(|| {
+        IO_APICS.lock().as_ref()
+            .map(|a| a.io_apics.iter().map(|io| (io.read(IOAPIC_REG_ID) >> 24) as u8 & 0xF).collect())
+            .unwrap_or_default()
+    })
+}
@@ -0,0 +1,87 @@
+//backtrace.rs
+
+use core::fmt;
+use x86_64::VirtAddr;
+
+use crate::memory;
+use crate::symbols;
+
+pub const MAX_FRAMES : usize = 16;
+
+/// Return addresses collected by following the saved frame pointer chain.
+/// Relies on the kernel being built with `-C force-frame-pointers=yes`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backtrace {
+    frames : [u64; MAX_FRAMES],
+    len    : usize
+}
+
+impl Backtrace {
+    /// Walks the stack starting from the caller's frame.
+    #[inline(always)]
+    pub fn capture() -> Backtrace {
+        let rbp : u64;
+        unsafe {
+            asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+        }
+        Backtrace::from_frame_pointer(rbp)
+    }
+
+    /// Each frame starts with the caller's saved `rbp`, followed by the
+    /// return address. The walk stops at the first frame that is null,
+    /// misaligned, unmapped or doesn't move up the stack.
+    pub fn from_frame_pointer(mut rbp : u64) -> Backtrace {
+        let mut backtrace = Backtrace { frames : [0; MAX_FRAMES], len : 0 };
+
+        while backtrace.len < MAX_FRAMES {
+            if rbp == 0 || rbp % 8 != 0 || !is_readable(rbp) || !is_readable(rbp + 8) {
+                break;
+            }
+
+            let return_address = unsafe { *((rbp + 8) as *const u64) };
+            if return_address == 0 {
+                break;
+            }
+            backtrace.frames[backtrace.len] = return_address;
+            backtrace.len += 1;
+
+            let next = unsafe { *(rbp as *const u64) };
+            if next <= rbp {
+                break;
+            }
+            rbp = next;
+        }
+
+        backtrace
+    }
+
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+        if self.len == 0 {
+            return writeln!(f, "  <no frames>");
+        }
+        if !symbols::is_loaded() {
+            writeln!(f, "  <no symbol table, the kernel wasn't linked by tools/link.sh>")?;
+        }
+        for (i, &address) in self.frames().iter().enumerate() {
+            match symbols::lookup_return_address(address) {
+                Some(symbol) => writeln!(f, "  #{:<2} 0x{:016x} {}", i, address, symbol)?,
+                None => writeln!(f, "  #{:<2} 0x{:016x}", i, address)?
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_readable(addr : u64) -> bool {
+    if VirtAddr::try_new(addr).is_err() {
+        return false;
+    }
+    memory::try_translate_addr(VirtAddr::new(addr)).is_some()
+}
@@ -0,0 +1,293 @@
+//exceptions.rs
+
+use core::fmt;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+use crate::backtrace::Backtrace;
+use crate::gdt;
+use crate::serial;
+use crate::symbols;
+use crate::terminal;
+
+pub fn register_handlers(idt : &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    unsafe {
+        idt.double_fault.set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_FIRST_INDEX);
+    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.security_exception.set_handler_fn(security_exception_handler);
+}
+
+/// Error code pushed by exceptions that relate to a segment selector or an
+/// IDT entry (#TS, #NP, #SS, #GP).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorErrorCode(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt
+}
+
+impl SelectorErrorCode {
+    pub fn new(error_code : u64) -> SelectorErrorCode {
+        SelectorErrorCode(error_code)
+    }
+
+    /// The exception was caused by an event external to the program,
+    /// e.g. a hardware interrupt.
+    pub fn external(&self) -> bool {
+        self.0 & 0b1 != 0
+    }
+
+    pub fn table(&self) -> DescriptorTable {
+        match (self.0 >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            _    => DescriptorTable::Idt
+        }
+    }
+
+    pub fn index(&self) -> u64 {
+        (self.0 >> 3) & 0x1FFF
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        if self.is_null() {
+            return write!(f, "Error Code: 0 (not selector related)");
+        }
+        write!(f, "Error Code: 0x{:x} ({:?} entry {}{})",
+            self.0,
+            self.table(),
+            self.index(),
+            if self.external() { ", external event" } else { "" }
+        )
+    }
+}
+
+struct PageFaultDescription(PageFaultErrorCode);
+
+impl fmt::Display for PageFaultDescription {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let code = self.0;
+        let cause = if code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            "protection violation"
+        } else {
+            "non-present page"
+        };
+        let access = if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            "instruction fetch"
+        } else if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            "write"
+        } else {
+            "read"
+        };
+        let mode = if code.contains(PageFaultErrorCode::USER_MODE) { "user" } else { "kernel" };
+
+        write!(f, "{} on a {} in {} mode", access, cause, mode)?;
+        if code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+            write!(f, ", reserved bit set in a page table")?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints an exception report to both the terminal and COM1. The handler
+/// returns to whatever it interrupted, so a writer that was mid-write keeps
+/// its lock and is left out of the report rather than deadlocking us.
+fn report(name : &str, frame : &InterruptStackFrame, details : Option<fmt::Arguments>) {
+    let to_serial = !serial::is_locked();
+    let to_terminal = !terminal::is_locked();
+
+    emit(to_serial, to_terminal, format_args!("EXCEPTION: {}", name));
+    if let Some(details) = details {
+        emit(to_serial, to_terminal, details);
+    }
+    if let Some(symbol) = symbols::lookup(frame.instruction_pointer.as_u64()) {
+        emit(to_serial, to_terminal, format_args!("In: {}", symbol));
+    }
+    emit(to_serial, to_terminal, format_args!("{:#?}", frame));
+}
+
+fn emit(to_serial : bool, to_terminal : bool, args : fmt::Arguments) {
+    if to_serial {
+        serial::println!("{}", args);
+    }
+    if to_terminal {
+        terminal::error!("{}", args);
+    }
+}
+
+/// Reports an exception we can't return from and halts the CPU.
+fn fatal(name : &str, frame : &InterruptStackFrame, details : Option<fmt::Arguments>) -> ! {
+    crate::disable_interrupts();
+    //Nothing we interrupted will run again, so take its locks, like the
+    //panic handler does.
+    unsafe {
+        serial::SERIAL1.force_unlock();
+        terminal::force_unlock();
+    }
+    report(name, frame, details);
+
+    let backtrace = Backtrace::capture();
+    emit(true, true, format_args!("{}", backtrace));
+    crate::spin!();
+}
+
+extern "x86-interrupt" fn divide_error_handler(frame : &mut InterruptStackFrame) {
+    fatal("DIVIDE ERROR", frame, None);
+}
+
+extern "x86-interrupt" fn debug_handler(frame : &mut InterruptStackFrame) {
+    report("DEBUG", frame, None);
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(frame : &mut InterruptStackFrame) {
+    report("NON-MASKABLE INTERRUPT", frame, None);
+}
+
+extern "x86-interrupt" fn breakpoint_handler(frame : &mut InterruptStackFrame) {
+    report("BREAKPOINT", frame, None);
+}
+
+extern "x86-interrupt" fn overflow_handler(frame : &mut InterruptStackFrame) {
+    report("OVERFLOW", frame, None);
+}
+
+extern "x86-interrupt" fn bound_range_exceeded_handler(frame : &mut InterruptStackFrame) {
+    fatal("BOUND RANGE EXCEEDED", frame, None);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(frame : &mut InterruptStackFrame) {
+    fatal("INVALID OPCODE", frame, None);
+}
+
+extern "x86-interrupt" fn device_not_available_handler(frame : &mut InterruptStackFrame) {
+    fatal("DEVICE NOT AVAILABLE", frame, None);
+}
+
+extern "x86-interrupt" fn double_fault_handler(frame : &mut InterruptStackFrame,
+_error_code : u64) -> ! {
+    fatal("DOUBLE FAULT", frame, None);
+}
+
+extern "x86-interrupt" fn invalid_tss_handler(frame : &mut InterruptStackFrame,
+error_code : u64) {
+    let code = SelectorErrorCode::new(error_code);
+    fatal("INVALID TSS", frame, Some(format_args!("{}", code)));
+}
+
+extern "x86-interrupt" fn segment_not_present_handler(frame : &mut InterruptStackFrame,
+error_code : u64) {
+    let code = SelectorErrorCode::new(error_code);
+    fatal("SEGMENT NOT PRESENT", frame, Some(format_args!("{}", code)));
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler(frame : &mut InterruptStackFrame,
+error_code : u64) {
+    let code = SelectorErrorCode::new(error_code);
+    fatal("STACK SEGMENT FAULT", frame, Some(format_args!("{}", code)));
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(frame : &mut InterruptStackFrame,
+error_code : u64) {
+    let code = SelectorErrorCode::new(error_code);
+    fatal("GENERAL PROTECTION FAULT", frame, Some(format_args!("{}", code)));
+}
+
+extern "x86-interrupt" fn page_fault_handler(frame : &mut InterruptStackFrame,
+error_code : PageFaultErrorCode) {
+    let address = Cr2::read();
+    fatal("PAGE FAULT", frame, Some(format_args!(
+        "Accessed Address: {:?}\nError Code: {:?} ({})",
+        address, error_code, PageFaultDescription(error_code)
+    )));
+}
+
+extern "x86-interrupt" fn x87_floating_point_handler(frame : &mut InterruptStackFrame) {
+    fatal("x87 FLOATING POINT", frame, None);
+}
+
+extern "x86-interrupt" fn alignment_check_handler(frame : &mut InterruptStackFrame,
+error_code : u64) {
+    fatal("ALIGNMENT CHECK", frame, Some(format_args!("Error Code: 0x{:x}", error_code)));
+}
+
+extern "x86-interrupt" fn machine_check_handler(frame : &mut InterruptStackFrame) -> ! {
+    fatal("MACHINE CHECK", frame, None);
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler(frame : &mut InterruptStackFrame) {
+    fatal("SIMD FLOATING POINT", frame, None);
+}
+
+extern "x86-interrupt" fn virtualization_handler(frame : &mut InterruptStackFrame) {
+    fatal("VIRTUALIZATION", frame, None);
+}
+
+extern "x86-interrupt" fn security_exception_handler(frame : &mut InterruptStackFrame,
+error_code : u64) {
+    fatal("SECURITY EXCEPTION", frame, Some(format_args!("Error Code: 0x{:x}", error_code)));
+}
+
+#[test_case]
+fn test_selector_error_code() {
+    //GDT entry 2, raised by an external event.
+    let code = SelectorErrorCode::new(0x11);
+    assert!(code.external());
+    assert_eq!(code.table(), DescriptorTable::Gdt);
+    assert_eq!(code.index(), 2);
+
+    //IDT vector 13, either IDT encoding.
+    assert_eq!(SelectorErrorCode::new(0x6A).table(), DescriptorTable::Idt);
+    assert_eq!(SelectorErrorCode::new(0x6E).table(), DescriptorTable::Idt);
+    assert_eq!(SelectorErrorCode::new(0x6A).index(), 13);
+    assert!(!SelectorErrorCode::new(0x6A).external());
+
+    assert_eq!(SelectorErrorCode::new(0x0C).table(), DescriptorTable::Ldt);
+    assert_eq!(SelectorErrorCode::new(0xFFF8).index(), 0x1FFF);
+    assert!(SelectorErrorCode::new(0).is_null());
+}
+
+#[test_case]
+fn test_selector_error_code_display() {
+    use alloc::string::ToString;
+    assert_eq!(SelectorErrorCode::new(0).to_string(), "Error Code: 0 (not selector related)");
+    assert_eq!(SelectorErrorCode::new(0x11).to_string(), "Error Code: 0x11 (Gdt entry 2, external event)");
+    assert_eq!(SelectorErrorCode::new(0x6A).to_string(), "Error Code: 0x6a (Idt entry 13)");
+}
+
+#[test_case]
+fn test_page_fault_description() {
+    use alloc::string::ToString;
+    let describe = |bits : u64| PageFaultDescription(PageFaultErrorCode::from_bits_truncate(bits)).to_string();
+    assert_eq!(describe(0b0_0000), "read on a non-present page in kernel mode");
+    assert_eq!(describe(0b0_0011), "write on a protection violation in kernel mode");
+    assert_eq!(describe(0b1_0100), "instruction fetch on a non-present page in user mode");
+    assert_eq!(describe(0b0_1001), "read on a protection violation in kernel mode, reserved bit set in a page table");
+}
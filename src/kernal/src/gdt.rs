@@ -8,6 +8,17 @@ use x86_64::structures::gdt::SegmentSelector;
 
 pub const DOUBLE_FAULT_FIRST_INDEX : u16 = 0;
 
+const DOUBLE_FAULT_STACK_SIZE : usize = 4096 * 5;
+static mut DOUBLE_FAULT_STACK : [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+/// Bottom and top of the stack the double fault handler switches to, so a
+/// kernel stack overflow still has somewhere to push the exception frame.
+pub fn double_fault_stack_bounds() -> (VirtAddr, VirtAddr) {
+    let stack_start = VirtAddr::from_ptr(unsafe { &DOUBLE_FAULT_STACK });
+    let stack_end = stack_start + DOUBLE_FAULT_STACK_SIZE;
+    (stack_start, stack_end)
+}
+
 pub fn init_gdt() {
     use x86_64::instructions::segmentation::set_cs;
     use x86_64::instructions::tables::load_tss;
@@ -37,11 +48,7 @@ lazy_static! {
     static ref TSS : TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_FIRST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
+            let (_, stack_end) = double_fault_stack_bounds();
             stack_end
         };
         tss
@@ -0,0 +1,283 @@
+//input.rs
+
+use alloc::string::String;
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use pc_keyboard::DecodedKey;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::keyboard;
+use crate::mouse::MouseButton;
+use crate::terminal;
+
+pub const SCANCODE_BUFFER_SIZE : usize = 256;
+pub const EVENT_QUEUE_SIZE     : usize = 128;
+
+/// Single producer, single consumer ring of raw scancodes. The keyboard
+/// interrupt is the only producer; consumers take `READER` first, so the
+/// interrupt path never waits on anything.
+struct ScancodeRing {
+    buffer : [AtomicU8; SCANCODE_BUFFER_SIZE],
+    head   : AtomicUsize,
+    tail   : AtomicUsize
+}
+
+impl ScancodeRing {
+    const fn new() -> ScancodeRing {
+        ScancodeRing {
+            buffer : [EMPTY_SLOT; SCANCODE_BUFFER_SIZE],
+            head   : AtomicUsize::new(0),
+            tail   : AtomicUsize::new(0)
+        }
+    }
+
+    fn push(&self, scancode : u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) == SCANCODE_BUFFER_SIZE {
+            return false;
+        }
+        self.buffer[head % SCANCODE_BUFFER_SIZE].store(scancode, Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail == head {
+            return None;
+        }
+        let scancode = self.buffer[tail % SCANCODE_BUFFER_SIZE].load(Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(scancode)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tail.load(Ordering::Acquire) == self.head.load(Ordering::Acquire)
+    }
+}
+
+//`AtomicU8` isn't `Copy`, but a constant can still be repeated.
+const EMPTY_SLOT : AtomicU8 = AtomicU8::new(0);
+
+static RING : ScancodeRing = ScancodeRing::new();
+
+static READER  : Mutex<()> = Mutex::new(());
+static WAKER   : AtomicWaker = AtomicWaker::new();
+static DROPPED : AtomicU64 = AtomicU64::new(0);
+
+/// Called by the keyboard interrupt handler, so it must not block or allocate.
+pub(crate) fn push_scancode(scancode : u8) {
+    if RING.push(scancode) {
+        WAKER.wake();
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Scancodes thrown away because nobody was reading input.
+pub fn dropped_scancodes() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Input other than keys. Positions are in character cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    MouseButton { button : MouseButton, pressed : bool, x : usize, y : usize },
+    /// Positive `delta` is away from the user.
+    MouseScroll { delta : i8, x : usize, y : usize }
+}
+
+static EVENTS         : OnceCell<ArrayQueue<InputEvent>> = OnceCell::uninit();
+static DROPPED_EVENTS : AtomicU64 = AtomicU64::new(0);
+
+/// Allocates the event queue. Must run after the heap is set up and before
+/// any device that produces events.
+pub fn init() {
+    EVENTS.init_once(|| ArrayQueue::new(EVENT_QUEUE_SIZE));
+}
+
+/// Called from interrupt handlers, the queue is lock free.
+pub(crate) fn push_event(event : InputEvent) {
+    let queued = EVENTS.get().map_or(false, |events| events.push(event).is_ok());
+    if !queued {
+        DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Events thrown away because the queue was full.
+pub fn dropped_events() -> u64 {
+    DROPPED_EVENTS.load(Ordering::Relaxed)
+}
+
+pub fn try_read_event() -> Option<InputEvent> {
+    EVENTS.get()?.pop().ok()
+}
+
+/// Waits for the next event.
+pub fn read_event() -> InputEvent {
+    loop {
+        if let Some(event) = try_read_event() {
+            return event;
+        }
+        wait_until(|| EVENTS.get().map_or(true, |events| events.is_empty()));
+    }
+}
+
+/// Returns the next key if one has been typed, without waiting.
+pub fn try_read_key() -> Option<DecodedKey> {
+    let _reader = READER.lock();
+    //Several scancodes can make up one key, and some don't produce one.
+    while let Some(scancode) = RING.pop() {
+        if let Some(key) = keyboard::decode_scancode(scancode) {
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// Waits for the next key.
+pub fn read_key() -> DecodedKey {
+    loop {
+        if let Some(key) = try_read_key() {
+            return key;
+        }
+        wait_until(|| RING.is_empty());
+    }
+}
+
+/// Waits for the next key that produces a character.
+pub fn read_char() -> char {
+    loop {
+        if let DecodedKey::Unicode(c) = read_key() {
+            return c;
+        }
+    }
+}
+
+/// What `read_line` shows as the user types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Echo {
+    Off,
+    On,
+    /// Shows this character in place of each one typed, for passwords.
+    Masked(char)
+}
+
+/// Reads characters into `buf` until Enter, handling backspace, and returns
+/// the number of bytes appended. Like `std`, the newline is kept.
+pub fn read_line(buf : &mut String, echo : Echo) -> usize {
+    let start = buf.len();
+    loop {
+        match read_char() {
+            '\n' => {
+                buf.push('\n');
+                if echo != Echo::Off {
+                    terminal::newline();
+                }
+                return buf.len() - start;
+            }
+            '\u{8}' => {
+                if buf.len() > start {
+                    buf.pop();
+                    if echo != Echo::Off {
+                        terminal::backspace();
+                    }
+                }
+            }
+            c => {
+                buf.push(c);
+                match echo {
+                    Echo::Off => {}
+                    Echo::On => terminal::print!("{}", c),
+                    Echo::Masked(mask) => terminal::print!("{}", mask)
+                }
+            }
+        }
+    }
+}
+
+/// Halts until the next interrupt while `nothing_waiting` holds, without a
+/// window for input to slip in between the check and the `hlt`.
+fn wait_until(nothing_waiting : impl Fn() -> bool) {
+    assert!(interrupts::are_enabled(), "Waiting for input with interrupts disabled would never wake");
+    interrupts::disable();
+    if nothing_waiting() {
+        interrupts::enable_and_hlt();
+    } else {
+        interrupts::enable();
+    }
+}
+
+/// Keys as an async stream, for tasks on the executor. Only the most recently
+/// polled stream is woken, so keep to one at a time.
+pub struct KeyStream {
+    _private : ()
+}
+
+pub fn keys() -> KeyStream {
+    KeyStream { _private : () }
+}
+
+impl Stream for KeyStream {
+    type Item = DecodedKey;
+
+    fn poll_next(self : Pin<&mut Self>, context : &mut Context) -> Poll<Option<DecodedKey>> {
+        if let Some(key) = try_read_key() {
+            return Poll::Ready(Some(key));
+        }
+
+        //Register before checking again, so a scancode pushed in between
+        //still wakes us.
+        WAKER.register(&context.waker());
+        match try_read_key() {
+            Some(key) => {
+                WAKER.take();
+                Poll::Ready(Some(key))
+            }
+            None => Poll::Pending
+        }
+    }
+}
+
+#[test_case]
+fn test_ring_wraps() {
+    let ring = ScancodeRing::new();
+    ring.head.store(usize::MAX - 1, Ordering::Relaxed);
+    ring.tail.store(usize::MAX - 1, Ordering::Relaxed);
+    for i in 0..10 {
+        assert!(ring.push(i));
+        assert_eq!(ring.pop(), Some(i));
+    }
+    assert_eq!(ring.pop(), None);
+}
+
+#[test_case]
+fn test_ring_full() {
+    let ring = ScancodeRing::new();
+    for i in 0..SCANCODE_BUFFER_SIZE {
+        assert!(ring.push(i as u8));
+    }
+    assert!(!ring.push(0));
+    assert_eq!(ring.pop(), Some(0));
+    assert!(ring.push(0));
+}
+
+#[test_case]
+fn test_scancodes_decode_to_keys() {
+    //Set 1 make and break codes for A, whatever set `ps2` picked.
+    let set = keyboard::scancode_set();
+    keyboard::set_scancode_set(keyboard::ScancodeSet::Set1);
+    push_scancode(0x1E);
+    push_scancode(0x9E);
+    assert_eq!(try_read_key(), Some(DecodedKey::Unicode('a')));
+    assert_eq!(try_read_key(), None);
+    keyboard::set_scancode_set(set);
+}
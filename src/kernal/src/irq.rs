@@ -0,0 +1,195 @@
+//irq.rs
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame};
+
+use crate::pics::{self, InterruptIndex};
+
+pub const IRQ_LINES : u8 = 16;
+
+/// Called in interrupt context with the line's EOI still pending, so it must
+/// be quick and must not block or allocate.
+pub type IrqHandler = fn();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// Not one of the sixteen ISA lines.
+    InvalidLine(u8),
+    /// Owned by the kernel itself: the cascade can't fire, and the timer
+    /// can't be masked as it drives the scheduler.
+    Reserved(u8),
+    AlreadyRegistered(u8)
+}
+
+//`fn()` pointers stored as usize, 0 for none, so dispatch never takes a lock.
+static HANDLERS : [AtomicUsize; IRQ_LINES as usize] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)
+];
+
+static COUNTS : [AtomicU64; IRQ_LINES as usize] = [
+    AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0),
+    AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0),
+    AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0),
+    AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)
+];
+
+static SPURIOUS : AtomicU64 = AtomicU64::new(0);
+
+fn check_line(irq : u8) -> Result<(), IrqError> {
+    if irq >= IRQ_LINES {
+        return Err(IrqError::InvalidLine(irq));
+    }
+    if irq == InterruptIndex::Cascade.irq() {
+        return Err(IrqError::Reserved(irq));
+    }
+    Ok(())
+}
+
+fn check_maskable(irq : u8) -> Result<(), IrqError> {
+    check_line(irq)?;
+    if irq == InterruptIndex::Timer.irq() {
+        return Err(IrqError::Reserved(irq));
+    }
+    Ok(())
+}
+
+/// Installs `handler` for `irq` and unmasks the line. The EOI is sent for
+/// the handler once it returns.
+///
+/// The timer line stays unmasked for the scheduler, an IRQ 0 handler is
+/// called after each scheduler tick.
+pub fn register(irq : u8, handler : IrqHandler) -> Result<(), IrqError> {
+    check_line(irq)?;
+    HANDLERS[irq as usize]
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .map_err(|_| IrqError::AlreadyRegistered(irq))?;
+    if irq != InterruptIndex::Timer.irq() {
+        pics::unmask(irq);
+    }
+    Ok(())
+}
+
+/// Masks `irq` and removes its handler. The timer line is left unmasked.
+pub fn unregister(irq : u8) -> Result<(), IrqError> {
+    check_line(irq)?;
+    if irq != InterruptIndex::Timer.irq() {
+        pics::mask(irq);
+    }
+    HANDLERS[irq as usize].store(0, Ordering::Release);
+    Ok(())
+}
+
+pub fn mask(irq : u8) -> Result<(), IrqError> {
+    check_maskable(irq)?;
+    pics::mask(irq);
+    Ok(())
+}
+
+pub fn unmask(irq : u8) -> Result<(), IrqError> {
+    check_maskable(irq)?;
+    pics::unmask(irq);
+    Ok(())
+}
+
+pub fn is_registered(irq : u8) -> bool {
+    handler(irq).is_some()
+}
+
+/// The handler installed for `irq`, so it can be put back after borrowing
+/// the line.
+pub fn handler(irq : u8) -> Option<IrqHandler> {
+    if irq >= IRQ_LINES {
+        return None;
+    }
+    match HANDLERS[irq as usize].load(Ordering::Acquire) {
+        0 => None,
+        handler => Some(unsafe { core::mem::transmute::<usize, IrqHandler>(handler) })
+    }
+}
+
+/// Interrupts seen on `irq`, spurious ones excluded.
+pub fn count(irq : u8) -> u64 {
+    COUNTS.get(irq as usize).map_or(0, |c| c.load(Ordering::Relaxed))
+}
+
+pub fn spurious_count() -> u64 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+fn dispatch(irq : u8) {
+    if pics::is_spurious(irq) {
+        SPURIOUS.fetch_add(1, Ordering::Relaxed);
+        pics::end_spurious_interrupt(irq);
+        return;
+    }
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+
+    let handler = HANDLERS[irq as usize].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler : IrqHandler = unsafe { core::mem::transmute(handler) };
+        handler();
+    }
+
+    if let Some(index) = InterruptIndex::from_irq(irq) {
+        pics::clear_interrupt(index);
+    }
+}
+
+/// Runs the IRQ 0 handler, if there is one. Called by the scheduler's tick,
+/// which owns the timer vector and sends its EOI.
+pub(crate) fn chain_timer() {
+    let irq = InterruptIndex::Timer.irq();
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+    if let Some(handler) = handler(irq) {
+        handler();
+    }
+}
+
+/// Generates the IDT entry for one IRQ line, which hands off to `dispatch`.
+macro irq_stub($name:ident, $irq:expr) {
+    extern "x86-interrupt" fn $name(_stack_frame : &mut InterruptStackFrame) {
+        dispatch($irq);
+    }
+}
+
+irq_stub!(irq_1, 1);
+irq_stub!(irq_2, 2);
+irq_stub!(irq_3, 3);
+irq_stub!(irq_4, 4);
+irq_stub!(irq_5, 5);
+irq_stub!(irq_6, 6);
+irq_stub!(irq_7, 7);
+irq_stub!(irq_8, 8);
+irq_stub!(irq_9, 9);
+irq_stub!(irq_10, 10);
+irq_stub!(irq_11, 11);
+irq_stub!(irq_12, 12);
+irq_stub!(irq_13, 13);
+irq_stub!(irq_14, 14);
+irq_stub!(irq_15, 15);
+
+//IRQ 0 is left out, the timer vector belongs to the scheduler.
+static STUBS : [(u8, HandlerFunc); 15] = [
+    (1, irq_1), (2, irq_2), (3, irq_3), (4, irq_4), (5, irq_5),
+    (6, irq_6), (7, irq_7), (8, irq_8), (9, irq_9), (10, irq_10),
+    (11, irq_11), (12, irq_12), (13, irq_13), (14, irq_14), (15, irq_15)
+];
+
+/// Points the IDT entries of IRQs 1-15 at the dispatcher.
+pub fn install(idt : &mut InterruptDescriptorTable) {
+    for &(irq, stub) in STUBS.iter() {
+        let index = InterruptIndex::from_irq(irq).expect("IRQ stub without a vector");
+        idt[index.as_usize()].set_handler_fn(stub);
+    }
+}
+
+#[test_case]
+fn test_reserved_lines() {
+    assert_eq!(mask(0), Err(IrqError::Reserved(0)));
+    assert_eq!(unmask(0), Err(IrqError::Reserved(0)));
+    assert_eq!(register(2, || {}), Err(IrqError::Reserved(2)));
+    assert_eq!(register(16, || {}), Err(IrqError::InvalidLine(16)));
+}
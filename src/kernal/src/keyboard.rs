@@ -1,15 +1,56 @@
+use x86_64::instructions::interrupts;
 use x86_64::instructions::port::Port;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1, KeyEvent, KeyCode};
+use pc_keyboard::{DecodeState, DecodedKey, Error, HandleControl, Keyboard, ScancodeSet1, ScancodeSet2, KeyEvent, KeyCode, KeyState};
+//The trait, our `ScancodeSet` names the set in use.
+use pc_keyboard::ScancodeSet as _;
 use spin::Mutex;
 use lazy_static::lazy_static;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::input;
+use crate::irq;
+use crate::mouse;
+use crate::power;
+use crate::ps2;
+use crate::serial;
+use crate::timer;
+use crate::tsc;
+
+pub mod layout;
+
+pub use layout::{DynamicLayout, KeyMapping, Keymap, Layout, LayoutError};
 
 lazy_static! {
-    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-        Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1,
+    static ref KEYBOARD: Mutex<Keyboard<DynamicLayout, DynamicScancodeSet>> =
+        Mutex::new(Keyboard::new(DynamicLayout, DynamicScancodeSet,
             HandleControl::Ignore)
         );
 }
 
+pub static KEYBOARD_IRQ : u8 = 1;
+
+pub fn init() {
+    serial::print!("Registering Keyboard IRQ...");
+    irq::register(KEYBOARD_IRQ, handle_interrupt).expect("Keyboard IRQ already taken");
+    serial::println!("[OK]");
+}
+
+/// Command responses are handed to whoever is waiting in `send_command`
+/// rather than being decoded as keys.
+fn handle_interrupt() {
+    let byte = read_scancode();
+    if byte == COMMAND_ACK || byte == COMMAND_RESEND {
+        RESPONSE.store(byte, Ordering::Release);
+    } else {
+        queue_scancode(byte);
+    }
+}
+
+fn queue_scancode(byte : u8) {
+    watch_for_reboot(byte);
+    input::push_scancode(byte);
+}
+
 pub fn read_scancode() -> u8 {
     unsafe {
         let mut port = Port::new(KEYBOARD_PORT);
@@ -18,14 +59,275 @@ pub fn read_scancode() -> u8 {
 }
 
 pub fn read_key() -> Option<DecodedKey> {
-    let scancode = read_scancode();
+    decode_scancode(read_scancode())
+}
+
+/// Feeds one scancode byte to the decoder, returning a key once a complete
+/// key event has been seen. Must be called from task context, toggling a lock
+/// key waits for the keyboard to acknowledge the new LED state.
+pub fn decode_scancode(scancode : u8) -> Option<DecodedKey> {
     let mut keyboard = KEYBOARD.lock();
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            return Some(key);
+    let key_event = match keyboard.add_byte(scancode) {
+        Ok(Some(key_event)) => key_event,
+        _ => return None
+    };
+
+    let (state, tracked) = track(&key_event);
+    //`pc_keyboard` toggles Caps and Num Lock on every typematic repeat.
+    if tracked == Tracked::LockRepeat {
+        return None;
+    }
+    let key = keyboard.process_keyevent(key_event);
+    drop(keyboard);
+
+    if tracked == Tracked::LocksChanged {
+        if let Err(error) = set_leds_state(state.leds()) {
+            serial::println!("Keyboard: LED update failed: {:?}", error);
+        }
+    }
+    key
+}
+
+/// Tracks just enough of the scancode stream to spot Ctrl+Alt+Del as the
+/// bytes arrive, so it works even when nothing is reading input.
+struct RebootWatch {
+    decode : DecodeState,
+    ctrl   : u8,
+    alt    : u8
+}
+
+static REBOOT_WATCH   : Mutex<RebootWatch> = Mutex::new(RebootWatch {
+    decode : DecodeState::Start, ctrl : 0, alt : 0
+});
+static REBOOT_PENDING : AtomicBool = AtomicBool::new(false);
+
+//Left and right modifier bits.
+static LEFT  : u8 = 0b01;
+static RIGHT : u8 = 0b10;
+
+/// Runs with interrupts off, so the reboot itself is left to the timer
+/// thread, or done here if the timers aren't up yet.
+fn watch_for_reboot(byte : u8) {
+    let requested = interrupts::without_interrupts(|| {
+        let mut watch = REBOOT_WATCH.lock();
+        let event = match DynamicScancodeSet::advance_state(&mut watch.decode, byte) {
+            Ok(Some(event)) => event,
+            _ => return false
+        };
+        let down = event.state == KeyState::Down;
+        let update = |held : &mut u8, bit : u8| if down { *held |= bit } else { *held &= !bit };
+        match event.code {
+            KeyCode::ControlLeft => update(&mut watch.ctrl, LEFT),
+            KeyCode::ControlRight => update(&mut watch.ctrl, RIGHT),
+            KeyCode::AltLeft => update(&mut watch.alt, LEFT),
+            KeyCode::AltRight => update(&mut watch.alt, RIGHT),
+            KeyCode::Delete => return down && watch.ctrl != 0 && watch.alt != 0,
+            _ => {}
+        }
+        false
+    });
+    if requested {
+        REBOOT_PENDING.store(true, Ordering::Release);
+        if !timer::nudge() {
+            power::reboot();
         }
     }
-    return None;
+}
+
+/// Called by the timer thread.
+pub(crate) fn reboot_if_requested() {
+    if REBOOT_PENDING.load(Ordering::Acquire) {
+        power::reboot();
+    }
+}
+
+/// The scancode set reaching us, after any translation by the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2
+}
+
+//Set 1 until `ps2::init` says otherwise, which is what the firmware leaves us.
+static SCANCODE_SET : AtomicU8 = AtomicU8::new(1);
+
+/// Decodes whichever set `set_scancode_set` picked, the same way
+/// `DynamicLayout` stands in for the layout.
+pub struct DynamicScancodeSet;
+
+impl pc_keyboard::ScancodeSet for DynamicScancodeSet {
+    fn advance_state(state : &mut DecodeState, code : u8) -> Result<Option<KeyEvent>, Error> {
+        match scancode_set() {
+            ScancodeSet::Set1 => ScancodeSet1::advance_state(state, code),
+            ScancodeSet::Set2 => ScancodeSet2::advance_state(state, code)
+        }
+    }
+
+    fn map_scancode(code : u8) -> Result<KeyCode, Error> {
+        match scancode_set() {
+            ScancodeSet::Set1 => ScancodeSet1::map_scancode(code),
+            ScancodeSet::Set2 => ScancodeSet2::map_scancode(code)
+        }
+    }
+
+    fn map_extended_scancode(code : u8) -> Result<KeyCode, Error> {
+        match scancode_set() {
+            ScancodeSet::Set1 => ScancodeSet1::map_extended_scancode(code),
+            ScancodeSet::Set2 => ScancodeSet2::map_extended_scancode(code)
+        }
+    }
+}
+
+/// Only changes how scancodes are decoded, the keyboard itself is switched
+/// by `ps2`.
+pub fn set_scancode_set(set : ScancodeSet) {
+    SCANCODE_SET.store(match set {
+        ScancodeSet::Set1 => 1,
+        ScancodeSet::Set2 => 2
+    }, Ordering::Relaxed);
+    //Drop any half decoded sequence from the old set.
+    KEYBOARD.lock().clear();
+    interrupts::without_interrupts(|| {
+        REBOOT_WATCH.lock().decode = DecodeState::Start;
+    });
+}
+
+pub fn scancode_set() -> ScancodeSet {
+    match SCANCODE_SET.load(Ordering::Relaxed) {
+        2 => ScancodeSet::Set2,
+        _ => ScancodeSet::Set1
+    }
+}
+
+/// Switches to a layout by name ("us", "uk", "de", "dvorak" or a registered
+/// keymap). Takes effect from the next key.
+pub fn set_layout(name : &str) -> Result<(), LayoutError> {
+    let layout = layout::find(name).ok_or(LayoutError::UnknownLayout)?;
+    layout::set(layout);
+    serial::println!("Keyboard layout: {}", layout.name());
+    Ok(())
+}
+
+pub fn layout_name() -> &'static str {
+    layout::current().name()
+}
+
+pub fn register_layout(keymap : &'static Keymap) -> Result<(), LayoutError> {
+    layout::register(keymap)
+}
+
+/// `MapLettersToUnicode` turns Ctrl+A..Z into U+0001..U+001A, `Ignore`
+/// leaves the letters alone.
+pub fn set_handle_control(handle_ctrl : HandleControl) {
+    KEYBOARD.lock().set_ctrl_handling(handle_ctrl);
+}
+
+pub fn handle_control() -> HandleControl {
+    KEYBOARD.lock().get_ctrl_handling()
+}
+
+/// Modifier and lock keys as last seen by `decode_scancode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardState {
+    pub left_shift  : bool,
+    pub right_shift : bool,
+    pub left_ctrl   : bool,
+    pub right_ctrl  : bool,
+    pub left_alt    : bool,
+    pub right_alt   : bool,
+    pub caps_lock   : bool,
+    pub num_lock    : bool,
+    pub scroll_lock : bool
+}
+
+impl KeyboardState {
+    //Num Lock starts on to match `pc_keyboard`.
+    const fn new() -> KeyboardState {
+        KeyboardState {
+            left_shift  : false,
+            right_shift : false,
+            left_ctrl   : false,
+            right_ctrl  : false,
+            left_alt    : false,
+            right_alt   : false,
+            caps_lock   : false,
+            num_lock    : true,
+            scroll_lock : false
+        }
+    }
+
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    pub fn alt(&self) -> bool {
+        self.left_alt || self.right_alt
+    }
+
+    /// The lock state in the format of the set LEDs command.
+    pub fn leds(&self) -> u8 {
+        let mut leds = 0;
+        if self.scroll_lock { leds |= LED_SCROLL_ON; }
+        if self.num_lock { leds |= LED_NUM_LOCK_ON; }
+        if self.caps_lock { leds |= LED_CAPS_LOCK_ON; }
+        leds
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tracked {
+    Other,
+    LocksChanged,
+    LockRepeat
+}
+
+static STATE : Mutex<KeyboardState> = Mutex::new(KeyboardState::new());
+//Lock keys currently held down, in LED bit order, to spot typematic repeats.
+static LOCKS_HELD : AtomicU8 = AtomicU8::new(0);
+
+pub fn state() -> KeyboardState {
+    interrupts::without_interrupts(|| *STATE.lock())
+}
+
+fn track(event : &KeyEvent) -> (KeyboardState, Tracked) {
+    let down = event.state == KeyState::Down;
+    interrupts::without_interrupts(|| {
+        let mut state = STATE.lock();
+        let lock = match event.code {
+            KeyCode::ShiftLeft    => { state.left_shift = down; None }
+            KeyCode::ShiftRight   => { state.right_shift = down; None }
+            KeyCode::ControlLeft  => { state.left_ctrl = down; None }
+            KeyCode::ControlRight => { state.right_ctrl = down; None }
+            KeyCode::AltLeft      => { state.left_alt = down; None }
+            KeyCode::AltRight     => { state.right_alt = down; None }
+            KeyCode::CapsLock     => Some(LED_CAPS_LOCK_ON),
+            KeyCode::NumpadLock   => Some(LED_NUM_LOCK_ON),
+            KeyCode::ScrollLock   => Some(LED_SCROLL_ON),
+            _ => None
+        };
+
+        let lock = match lock {
+            Some(lock) => lock,
+            None => return (*state, Tracked::Other)
+        };
+        if !down {
+            LOCKS_HELD.fetch_and(!lock, Ordering::Relaxed);
+            return (*state, Tracked::Other);
+        }
+        if LOCKS_HELD.fetch_or(lock, Ordering::Relaxed) & lock != 0 {
+            return (*state, Tracked::LockRepeat);
+        }
+        match event.code {
+            KeyCode::CapsLock   => state.caps_lock = !state.caps_lock,
+            KeyCode::NumpadLock => state.num_lock = !state.num_lock,
+            _                   => state.scroll_lock = !state.scroll_lock
+        }
+        (*state, Tracked::LocksChanged)
+    })
 }
 
 pub fn read_unicode_key() -> Option<char> {
@@ -50,19 +352,77 @@ pub fn read_rawkey() -> Option<KeyCode> {
     } 
 }
 
-pub fn set_leds_state(state : u8) {
-    unsafe {
-        let mut port = Port::new(KEYBOARD_PORT);
-        port.write(LED_STATE);
-        port.read();
-        port.write(state);
-        port.read();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardError {
+    /// The controller or the keyboard didn't respond in time.
+    Timeout,
+    /// The keyboard kept asking for the command to be resent.
+    Resend(u8)
+}
+
+static RESPONSE     : AtomicU8 = AtomicU8::new(0);
+static COMMAND_LOCK : Mutex<()> = Mutex::new(());
+
+/// Sets the Scroll, Num and Caps Lock LEDs from the `LED_*` bits. Waits for
+/// the keyboard to acknowledge, so it must not be called from an interrupt.
+pub fn set_leds_state(state : u8) -> Result<(), KeyboardError> {
+    let _command = COMMAND_LOCK.lock();
+    send_command(LED_STATE)?;
+    send_command(state & 0b111)
+}
+
+/// `rate` runs from 0 (30 keys a second) to 31 (2 a second), `delay` from
+/// 0 (250 ms) to 3 (1 s).
+pub fn set_typematic(rate : u8, delay : u8) -> Result<(), KeyboardError> {
+    let _command = COMMAND_LOCK.lock();
+    send_command(SET_TYPEMATIC)?;
+    send_command((delay & 0b11) << 5 | (rate & 0b1_1111))
+}
+
+/// Sends a byte to the keyboard, resending it while the keyboard asks for
+/// that, and waits for the ACK.
+pub fn send_command(byte : u8) -> Result<(), KeyboardError> {
+    for _ in 0..COMMAND_RETRIES {
+        RESPONSE.store(0, Ordering::Release);
+        ps2::write_data(byte).map_err(|_| KeyboardError::Timeout)?;
+        if wait_for_response()? == COMMAND_ACK {
+            return Ok(());
+        }
+    }
+    Err(KeyboardError::Resend(byte))
+}
+
+fn wait_for_response() -> Result<u8, KeyboardError> {
+    let deadline = tsc::Deadline::after(ps2::RESPONSE_TIMEOUT);
+    while !deadline.has_passed() {
+        let response = RESPONSE.swap(0, Ordering::AcqRel);
+        if response != 0 {
+            return Ok(response);
+        }
+        //With interrupts off nothing routes the response to us, so poll for it.
+        let status = ps2::read_status();
+        if !interrupts::are_enabled() && status & ps2::STATUS_OUTPUT_FULL != 0 {
+            let byte = read_scancode();
+            if status & ps2::STATUS_AUX_DATA != 0 {
+                mouse::receive(byte);
+            } else if byte == COMMAND_ACK || byte == COMMAND_RESEND {
+                return Ok(byte);
+            } else {
+                queue_scancode(byte);
+            }
+        }
+        core::hint::spin_loop();
     }
+    Err(KeyboardError::Timeout)
 }
 
 pub static KEYBOARD_PORT : u16 = 0x60;
 pub static LED_STATE : u8 = 0xED;
 pub static COMMAND_ACK : u8 = 0xFA;
+pub static COMMAND_RESEND : u8 = 0xFE;
+pub static SET_TYPEMATIC : u8 = 0xF3;
+
+const COMMAND_RETRIES : usize = 3;
 
 pub static LED_SCROLL_ON : u8 = 0b001;
 pub static LED_SCROLL_OFF : u8 = 0b000;
@@ -71,4 +431,29 @@ pub static LED_NUM_LOCK_ON : u8 = 0b010;
 pub static LED_NUM_LOCK_OFF : u8 = 0b000;
 
 pub static LED_CAPS_LOCK_ON : u8 = 0b100;
-pub static LED_CAPS_LOCK_OFF : u8 = 0b000;
\ No newline at end of file
+pub static LED_CAPS_LOCK_OFF : u8 = 0b000;
+#[test_case]
+fn test_leds() {
+    let mut state = KeyboardState::new();
+    assert_eq!(state.leds(), LED_NUM_LOCK_ON);
+    state.caps_lock = true;
+    state.scroll_lock = true;
+    assert_eq!(state.leds(), LED_CAPS_LOCK_ON | LED_NUM_LOCK_ON | LED_SCROLL_ON);
+}
+
+#[test_case]
+fn test_lock_key_repeat() {
+    let before = state().scroll_lock;
+    let press = KeyEvent::new(KeyCode::ScrollLock, KeyState::Down);
+    let release = KeyEvent::new(KeyCode::ScrollLock, KeyState::Up);
+
+    let (toggled, tracked) = track(&press);
+    assert_eq!(tracked, Tracked::LocksChanged);
+    assert_eq!(toggled.scroll_lock, !before);
+    assert_eq!(track(&press).1, Tracked::LockRepeat);
+    assert_eq!(state().scroll_lock, !before);
+
+    assert_eq!(track(&release).1, Tracked::Other);
+    assert_eq!(track(&press).0.scroll_lock, before);
+    track(&release);
+}
@@ -0,0 +1,275 @@
+//keyboard/layout.rs
+
+use alloc::vec::Vec;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyboardLayout, Modifiers};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// One key of a custom keymap, named by its position on a US keyboard.
+/// Caps Lock only applies to keys with `caps` set, which `letter` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyMapping {
+    pub code    : KeyCode,
+    pub normal  : char,
+    pub shifted : char,
+    pub alt_gr  : Option<char>,
+    pub caps    : bool
+}
+
+impl KeyMapping {
+    pub const fn new(code : KeyCode, normal : char, shifted : char) -> KeyMapping {
+        KeyMapping { code, normal, shifted, alt_gr : None, caps : false }
+    }
+
+    pub const fn letter(code : KeyCode, lower : char, upper : char) -> KeyMapping {
+        KeyMapping { code, normal : lower, shifted : upper, alt_gr : None, caps : true }
+    }
+
+    pub const fn with_alt_gr(self, alt_gr : char) -> KeyMapping {
+        KeyMapping { alt_gr : Some(alt_gr), ..self }
+    }
+}
+
+/// A layout described as differences from the US layout, keys that aren't
+/// listed behave as they do on `layouts::Us104Key`.
+#[derive(Debug)]
+pub struct Keymap {
+    pub name : &'static str,
+    pub keys : &'static [KeyMapping]
+}
+
+impl Keymap {
+    pub fn map_keycode(&self, keycode : KeyCode, modifiers : &Modifiers,
+    handle_ctrl : HandleControl) -> DecodedKey {
+        let key = match self.keys.iter().find(|k| k.code == keycode) {
+            Some(key) => key,
+            None => return <layouts::Us104Key as KeyboardLayout>::map_keycode(keycode, modifiers, handle_ctrl)
+        };
+
+        let map_to_unicode = handle_ctrl == HandleControl::MapLettersToUnicode;
+        if map_to_unicode && modifiers.is_ctrl() && key.normal.is_ascii_lowercase() {
+            return DecodedKey::Unicode((key.normal as u8 - b'a' + 1) as char);
+        }
+        if modifiers.alt_gr {
+            if let Some(c) = key.alt_gr {
+                return DecodedKey::Unicode(c);
+            }
+        }
+        let shifted = if key.caps { modifiers.is_caps() } else { modifiers.is_shifted() };
+        DecodedKey::Unicode(if shifted { key.shifted } else { key.normal })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Layout {
+    Us104,
+    Uk105,
+    Custom(&'static Keymap)
+}
+
+impl Layout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Us104 => "us",
+            Layout::Uk105 => "uk",
+            Layout::Custom(keymap) => keymap.name
+        }
+    }
+
+    pub fn map_keycode(&self, keycode : KeyCode, modifiers : &Modifiers,
+    handle_ctrl : HandleControl) -> DecodedKey {
+        match self {
+            Layout::Us104 => layouts::Us104Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Uk105 => layouts::Uk105Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Custom(keymap) => keymap.map_keycode(keycode, modifiers, handle_ctrl)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutError {
+    UnknownLayout,
+    AlreadyRegistered
+}
+
+static BUILT_IN : [Layout; 4] = [
+    Layout::Us104,
+    Layout::Uk105,
+    Layout::Custom(&GERMAN),
+    Layout::Custom(&DVORAK)
+];
+
+static CUSTOM  : Mutex<Vec<&'static Keymap>> = Mutex::new(Vec::new());
+static CURRENT : Mutex<Layout> = Mutex::new(Layout::Us104);
+
+/// `pc_keyboard` picks the layout through a type parameter, this one looks
+/// it up at runtime so it can be switched without rebuilding the decoder.
+pub struct DynamicLayout;
+
+impl KeyboardLayout for DynamicLayout {
+    fn map_keycode(keycode : KeyCode, modifiers : &Modifiers, handle_ctrl : HandleControl) -> DecodedKey {
+        let layout = *CURRENT.lock();
+        layout.map_keycode(keycode, modifiers, handle_ctrl)
+    }
+}
+
+pub fn find(name : &str) -> Option<Layout> {
+    if let Some(layout) = BUILT_IN.iter().find(|l| l.name() == name) {
+        return Some(*layout);
+    }
+    interrupts::without_interrupts(|| {
+        CUSTOM.lock().iter().find(|k| k.name == name).map(|k| Layout::Custom(k))
+    })
+}
+
+/// Makes a custom keymap available to `set_layout`.
+pub fn register(keymap : &'static Keymap) -> Result<(), LayoutError> {
+    interrupts::without_interrupts(|| {
+        let mut custom = CUSTOM.lock();
+        let taken = BUILT_IN.iter().any(|l| l.name() == keymap.name)
+            || custom.iter().any(|k| k.name == keymap.name);
+        if taken {
+            return Err(LayoutError::AlreadyRegistered);
+        }
+        custom.push(keymap);
+        Ok(())
+    })
+}
+
+pub fn set(layout : Layout) {
+    interrupts::without_interrupts(|| {
+        *CURRENT.lock() = layout;
+    });
+}
+
+pub fn current() -> Layout {
+    interrupts::without_interrupts(|| *CURRENT.lock())
+}
+
+pub fn names() -> Vec<&'static str> {
+    let mut names : Vec<&'static str> = BUILT_IN.iter().map(|l| l.name()).collect();
+    interrupts::without_interrupts(|| {
+        names.extend(CUSTOM.lock().iter().map(|k| k.name));
+    });
+    names
+}
+
+/// German QWERTZ, AltGr is the right Alt key.
+pub static GERMAN : Keymap = Keymap {
+    name : "de",
+    keys : &[
+        KeyMapping::new(KeyCode::BackTick, '^', '°'),
+        KeyMapping::new(KeyCode::Key2, '2', '"').with_alt_gr('²'),
+        KeyMapping::new(KeyCode::Key3, '3', '§').with_alt_gr('³'),
+        KeyMapping::new(KeyCode::Key6, '6', '&'),
+        KeyMapping::new(KeyCode::Key7, '7', '/').with_alt_gr('{'),
+        KeyMapping::new(KeyCode::Key8, '8', '(').with_alt_gr('['),
+        KeyMapping::new(KeyCode::Key9, '9', ')').with_alt_gr(']'),
+        KeyMapping::new(KeyCode::Key0, '0', '=').with_alt_gr('}'),
+        KeyMapping::new(KeyCode::Minus, 'ß', '?').with_alt_gr('\\'),
+        KeyMapping::new(KeyCode::Equals, '´', '`'),
+        KeyMapping::letter(KeyCode::Q, 'q', 'Q').with_alt_gr('@'),
+        KeyMapping::letter(KeyCode::E, 'e', 'E').with_alt_gr('€'),
+        KeyMapping::letter(KeyCode::Y, 'z', 'Z'),
+        KeyMapping::letter(KeyCode::BracketSquareLeft, 'ü', 'Ü'),
+        KeyMapping::new(KeyCode::BracketSquareRight, '+', '*').with_alt_gr('~'),
+        KeyMapping::letter(KeyCode::SemiColon, 'ö', 'Ö'),
+        KeyMapping::letter(KeyCode::Quote, 'ä', 'Ä'),
+        //Scancode set 1 reports the ISO '#' key as BackSlash, set 2 as HashTilde.
+        KeyMapping::new(KeyCode::BackSlash, '#', '\''),
+        KeyMapping::new(KeyCode::HashTilde, '#', '\''),
+        KeyMapping::letter(KeyCode::Z, 'y', 'Y'),
+        KeyMapping::letter(KeyCode::M, 'm', 'M').with_alt_gr('µ'),
+        KeyMapping::new(KeyCode::Comma, ',', ';'),
+        KeyMapping::new(KeyCode::Fullstop, '.', ':'),
+        KeyMapping::new(KeyCode::Slash, '-', '_')
+    ]
+};
+
+/// US Dvorak.
+pub static DVORAK : Keymap = Keymap {
+    name : "dvorak",
+    keys : &[
+        KeyMapping::new(KeyCode::Minus, '[', '{'),
+        KeyMapping::new(KeyCode::Equals, ']', '}'),
+        KeyMapping::new(KeyCode::Q, '\'', '"'),
+        KeyMapping::new(KeyCode::W, ',', '<'),
+        KeyMapping::new(KeyCode::E, '.', '>'),
+        KeyMapping::letter(KeyCode::R, 'p', 'P'),
+        KeyMapping::letter(KeyCode::T, 'y', 'Y'),
+        KeyMapping::letter(KeyCode::Y, 'f', 'F'),
+        KeyMapping::letter(KeyCode::U, 'g', 'G'),
+        KeyMapping::letter(KeyCode::I, 'c', 'C'),
+        KeyMapping::letter(KeyCode::O, 'r', 'R'),
+        KeyMapping::letter(KeyCode::P, 'l', 'L'),
+        KeyMapping::new(KeyCode::BracketSquareLeft, '/', '?'),
+        KeyMapping::new(KeyCode::BracketSquareRight, '=', '+'),
+        KeyMapping::letter(KeyCode::S, 'o', 'O'),
+        KeyMapping::letter(KeyCode::D, 'e', 'E'),
+        KeyMapping::letter(KeyCode::F, 'u', 'U'),
+        KeyMapping::letter(KeyCode::G, 'i', 'I'),
+        KeyMapping::letter(KeyCode::H, 'd', 'D'),
+        KeyMapping::letter(KeyCode::J, 'h', 'H'),
+        KeyMapping::letter(KeyCode::K, 't', 'T'),
+        KeyMapping::letter(KeyCode::L, 'n', 'N'),
+        KeyMapping::letter(KeyCode::SemiColon, 's', 'S'),
+        KeyMapping::new(KeyCode::Quote, '-', '_'),
+        KeyMapping::new(KeyCode::Z, ';', ':'),
+        KeyMapping::letter(KeyCode::X, 'q', 'Q'),
+        KeyMapping::letter(KeyCode::C, 'j', 'J'),
+        KeyMapping::letter(KeyCode::V, 'k', 'K'),
+        KeyMapping::letter(KeyCode::B, 'x', 'X'),
+        KeyMapping::letter(KeyCode::N, 'b', 'B'),
+        KeyMapping::letter(KeyCode::Comma, 'w', 'W'),
+        KeyMapping::letter(KeyCode::Fullstop, 'v', 'V'),
+        KeyMapping::letter(KeyCode::Slash, 'z', 'Z')
+    ]
+};
+
+#[cfg(test)]
+fn modifiers(shift : bool, ctrl : bool, alt_gr : bool) -> Modifiers {
+    Modifiers {
+        lshift : shift, rshift : false,
+        lctrl : ctrl, rctrl : false,
+        numlock : false, capslock : false,
+        alt_gr
+    }
+}
+
+#[test_case]
+fn test_custom_keymap() {
+    let plain = modifiers(false, false, false);
+    let shift = modifiers(true, false, false);
+    let alt_gr = modifiers(false, false, true);
+    assert_eq!(GERMAN.map_keycode(KeyCode::Y, &plain, HandleControl::Ignore), DecodedKey::Unicode('z'));
+    assert_eq!(GERMAN.map_keycode(KeyCode::Key7, &shift, HandleControl::Ignore), DecodedKey::Unicode('/'));
+    assert_eq!(GERMAN.map_keycode(KeyCode::Q, &alt_gr, HandleControl::Ignore), DecodedKey::Unicode('@'));
+    assert_eq!(DVORAK.map_keycode(KeyCode::Slash, &shift, HandleControl::Ignore), DecodedKey::Unicode('Z'));
+    //Unlisted keys come from the US layout.
+    assert_eq!(DVORAK.map_keycode(KeyCode::A, &plain, HandleControl::Ignore), DecodedKey::Unicode('a'));
+}
+
+#[test_case]
+fn test_custom_keymap_control() {
+    let ctrl = modifiers(false, true, false);
+    assert_eq!(DVORAK.map_keycode(KeyCode::Slash, &ctrl, HandleControl::MapLettersToUnicode),
+        DecodedKey::Unicode('\u{001A}'));
+    assert_eq!(DVORAK.map_keycode(KeyCode::Slash, &ctrl, HandleControl::Ignore), DecodedKey::Unicode('z'));
+}
+
+#[test_case]
+fn test_find_layout() {
+    assert_eq!(find("uk").map(|l| l.name()), Some("uk"));
+    assert_eq!(find("dvorak").map(|l| l.name()), Some("dvorak"));
+    assert!(find("klingon").is_none());
+    assert_eq!(register(&GERMAN), Err(LayoutError::AlreadyRegistered));
+}
+
+#[test_case]
+fn test_custom_keymap_caps_lock() {
+    let caps = Modifiers { capslock : true, ..modifiers(false, false, false) };
+    assert_eq!(GERMAN.map_keycode(KeyCode::Quote, &caps, HandleControl::Ignore), DecodedKey::Unicode('Ä'));
+    //'ß' has no capital on the key, Caps Lock leaves it alone.
+    assert_eq!(GERMAN.map_keycode(KeyCode::Minus, &caps, HandleControl::Ignore), DecodedKey::Unicode('ß'));
+    assert_eq!(DVORAK.map_keycode(KeyCode::Z, &caps, HandleControl::Ignore), DecodedKey::Unicode(';'));
+}
@@ -1,26 +1,134 @@
 #![no_std]
+#![cfg_attr(test, no_main)]
 #![feature(decl_macro)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![feature(asm)]
+#![feature(naked_functions)]
+#![feature(panic_info_message)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
 pub mod vga;
 pub mod terminal;
 pub mod interrupts;
+pub mod irq;
+pub mod exceptions;
+pub mod backtrace;
+pub mod symbols;
+pub mod panic;
 pub mod gdt;
 pub mod pics;
+pub mod apic;
+pub mod acpi;
 pub mod pit;
+pub mod rtc;
+pub mod speaker;
 pub mod keyboard;
+pub mod ps2;
+pub mod input;
+pub mod mouse;
 pub mod serial;
+pub mod memory;
+pub mod power;
+pub mod allocator;
+pub mod testing;
+pub mod task;
+pub mod thread;
+pub mod time;
+pub mod tsc;
+pub mod timer;
+
+use bootloader::BootInfo;
+
+#[cfg(test)]
+use core::panic::PanicInfo;
+
+#[cfg(test)]
+bootloader::entry_point!(test_kernel_main);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info : &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    spin!();
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
 
 pub fn post() {
     serial::print!("Running POST...");
+    if !check_heap() { post_fail!(POST_FAIL_HEAP); }
     serial::println!("[OK]");
+
+    let frames = memory::frame_stats();
+    let frame_kib = (memory::FRAME_SIZE / 1024) as usize;
+    serial::println!("Memory: {} KiB used, {} KiB free, {} KiB total",
+        frames.used * frame_kib,
+        frames.free * frame_kib,
+        frames.total * frame_kib
+    );
+    let heap = allocator::stats();
+    serial::println!("Heap ({}): {} bytes used, {} bytes free, {} free regions, {}% fragmented",
+        allocator::name(),
+        heap.used,
+        heap.free,
+        heap.free_regions,
+        heap.fragmentation()
+    );
 }
 
-pub fn init() {
+fn check_heap() -> bool {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use alloc::string::String;
+    use alloc::collections::BTreeMap;
+
+    let boxed = Box::new(42);
+
+    let mut vec = Vec::new();
+    for i in 0..100 {
+        vec.push(i);
+    }
+
+    let mut string = String::from("NP");
+    string.push_str("OS");
+
+    let mut map = BTreeMap::new();
+    map.insert(1, "one");
+    map.insert(2, "two");
+
+    *boxed == 42 &&
+    vec.iter().sum::<usize>() == 4950 &&
+    string == "NPOS" &&
+    map.get(&2) == Some(&"two")
+}
+
+pub fn init(boot_info : &'static BootInfo) {
     gdt::init_gdt();
     interrupts::init_idt();
     unsafe {
         pics::PICS.lock().initialize();
     }
+    keyboard::init();
+    memory::init(boot_info);
+    allocator::init_heap().expect("Heap Initialisation Failed");
+    thread::init();
+    input::init();
+    timer::init();
+    rtc::init();
+    tsc::init();
+    acpi::init();
+    ps2::init();
+    mouse::init();
+    apic::init(acpi::apic_config().unwrap_or_else(apic::ApicConfig::legacy));
 }
 
 pub fn enable_interrupts() {
@@ -42,17 +150,21 @@ pub macro spin() {
 pub fn wait_for_interrupt() { x86_64::instructions::hlt(); }
 
 pub fn pause_for(ticks : usize) {
-    for _ in 0..=ticks {
-        x86_64::instructions::hlt();
-    }
+    time::sleep_ticks(ticks as u64);
 }
 
 pub fn set_tick_rate(hertz : usize) {
     let mut reload_value : u16 = ((pit::FREQUENCY / hertz) & 0xFFFF) as u16;
     if reload_value < 18 {reload_value = 18;}
-    unsafe {
-        pit::set_reload_value(reload_value);
+    if apic::is_enabled() {
+        //The APIC timer stands in for the PIT, `time` still counts in PIT cycles.
+        apic::set_timer_frequency((pit::FREQUENCY / reload_value as usize) as u32);
+    } else {
+        unsafe {
+            pit::set_reload_value(reload_value);
+        }
     }
+    time::set_reload_value(reload_value);
 }
 
 pub macro post_fail($code:expr) {
@@ -70,4 +182,5 @@ pub fn crash() {
     };
 }
 
-pub static POST_FAIL_TERMINAL : usize = 0x0100;
\ No newline at end of file
+pub static POST_FAIL_TERMINAL : usize = 0x0100;
+pub static POST_FAIL_HEAP : usize = 0x0200;
\ No newline at end of file
@@ -0,0 +1,156 @@
+//memory.rs
+
+use bootloader::BootInfo;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate
+};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::serial;
+
+mod frame_allocator;
+
+pub use frame_allocator::{FrameAllocator, FrameStats, FRAME_SIZE};
+
+static MAPPER : Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static FRAME_ALLOCATOR : Mutex<Option<FrameAllocator>> = Mutex::new(None);
+static PHYSICAL_MEMORY_OFFSET : Mutex<Option<VirtAddr>> = Mutex::new(None);
+
+/// Virtual window device registers are mapped into by `map_mmio`.
+pub const MMIO_START : u64 = 0x_5555_5555_0000;
+pub const MMIO_SIZE  : u64 = 16 * 1024 * 1024;
+
+static NEXT_MMIO : Mutex<u64> = Mutex::new(MMIO_START);
+
+pub fn init(boot_info : &'static BootInfo) {
+    serial::print!("Initialising Page Tables...");
+    let physical_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { new_mapper(physical_offset) };
+
+    without_interrupts(|| {
+        *PHYSICAL_MEMORY_OFFSET.lock() = Some(physical_offset);
+        *MAPPER.lock() = Some(mapper);
+    });
+    serial::println!("[OK]");
+
+    serial::print!("Initialising Frame Allocator...");
+    let allocator = unsafe { FrameAllocator::new(&boot_info.memory_map, physical_offset) };
+    without_interrupts(|| {
+        *FRAME_ALLOCATOR.lock() = Some(allocator);
+    });
+    serial::println!("[OK]");
+}
+
+/// Builds a mapper over the active level 4 table.
+///
+/// The bootloader must have mapped the whole of physical memory at
+/// `physical_offset`, and this must only be called once, otherwise we end up
+/// with aliased `&mut` references to the same page table.
+pub unsafe fn new_mapper(physical_offset : VirtAddr) -> OffsetPageTable<'static> {
+    let level_4_table = active_level_4_table(physical_offset);
+    OffsetPageTable::new(level_4_table, physical_offset)
+}
+
+unsafe fn active_level_4_table(physical_offset : VirtAddr) -> &'static mut PageTable {
+    let (level_4_frame, _) = Cr3::read();
+
+    let phys = level_4_frame.start_address();
+    let virt = physical_offset + phys.as_u64();
+    let table_ptr : *mut PageTable = virt.as_mut_ptr();
+
+    &mut *table_ptr
+}
+
+pub fn physical_memory_offset() -> VirtAddr {
+    without_interrupts(|| {
+        PHYSICAL_MEMORY_OFFSET.lock().expect("Memory has not been initialised")
+    })
+}
+
+/// Returns the virtual address a physical address is visible at through the
+/// bootloader's physical memory mapping.
+pub fn phys_to_virt(addr : PhysAddr) -> VirtAddr {
+    physical_memory_offset() + addr.as_u64()
+}
+
+pub fn translate_addr(addr : VirtAddr) -> Option<PhysAddr> {
+    with_mapper(|mapper| mapper.translate_addr(addr))
+}
+
+/// Like `translate_addr`, but gives up instead of spinning if the mapper is
+/// locked, for use from panic and exception paths.
+pub fn try_translate_addr(addr : VirtAddr) -> Option<PhysAddr> {
+    let mapper = MAPPER.try_lock()?;
+    mapper.as_ref()?.translate_addr(addr)
+}
+
+/// Runs `f` with exclusive access to the kernel's page table mapper.
+pub fn with_mapper<F, R>(f : F) -> R
+    where F : FnOnce(&mut OffsetPageTable<'static>) -> R
+{
+    without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        f(mapper.as_mut().expect("Memory has not been initialised"))
+    })
+}
+
+/// Runs `f` with exclusive access to the physical frame allocator.
+pub fn with_frame_allocator<F, R>(f : F) -> R
+    where F : FnOnce(&mut FrameAllocator) -> R
+{
+    without_interrupts(|| {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        f(allocator.as_mut().expect("Memory has not been initialised"))
+    })
+}
+
+pub fn allocate_frame() -> Option<PhysFrame> {
+    with_frame_allocator(|allocator| allocator.allocate())
+}
+
+pub fn deallocate_frame(frame : PhysFrame) {
+    with_frame_allocator(|allocator| allocator.deallocate(frame));
+}
+
+pub fn frame_stats() -> FrameStats {
+    with_frame_allocator(|allocator| allocator.stats())
+}
+
+/// Maps `size` bytes of device memory at `addr` uncached into the MMIO
+/// window and returns the virtual address of `addr`. Mappings are permanent.
+pub fn map_mmio(addr : PhysAddr, size : usize) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(addr);
+    let last = PhysFrame::<Size4KiB>::containing_address(addr + (size.max(1) - 1) as u64);
+    let frames = PhysFrame::range_inclusive(first, last);
+    let length = (last.start_address() - first.start_address()) + FRAME_SIZE;
+
+    let start = without_interrupts(|| {
+        let mut next = NEXT_MMIO.lock();
+        let start = *next;
+        if start + length > MMIO_START + MMIO_SIZE {
+            return None;
+        }
+        *next += length;
+        Some(start)
+    }).ok_or(MapToError::FrameAllocationFailed)?;
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    with_mapper(|mapper| {
+        with_frame_allocator(|allocator| {
+            for (i, frame) in frames.enumerate() {
+                let page = Page::containing_address(VirtAddr::new(start + i as u64 * FRAME_SIZE));
+                unsafe {
+                    mapper.map_to(page, frame, flags, allocator)?.flush();
+                }
+            }
+            Ok(())
+        })
+    })?;
+
+    Ok(VirtAddr::new(start + (addr - first.start_address())))
+}
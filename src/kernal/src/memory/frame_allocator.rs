@@ -0,0 +1,178 @@
+//memory/frame_allocator.rs
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::structures::paging::{PhysFrame, Size4KiB, FrameDeallocator};
+use x86_64::structures::paging::FrameAllocator as PagingFrameAllocator;
+
+pub const FRAME_SIZE : u64 = 4096;
+
+//The real mode IVT, the BIOS data area, the VGA buffer at 0xB8000 and the
+//option ROMs all live in the first MiB, none of it is ever handed out.
+const LOW_MEMORY_END : u64 = 0x10_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub total : usize,
+    pub used  : usize,
+    pub free  : usize
+}
+
+/// Bitmap allocator over the 4KiB frames the bootloader reported as usable.
+///
+/// A set bit means the frame is in use; everything the memory map does not
+/// mark as `Usable` (kernel, page tables, bootloader, ...) starts out set and
+/// is never cleared.
+pub struct FrameAllocator {
+    memory_map   : &'static MemoryMap,
+    bitmap       : &'static mut [u64],
+    bitmap_start : u64,
+    bitmap_end   : u64,
+    total_frames : usize,
+    used_frames  : usize,
+    next_free    : usize
+}
+
+impl FrameAllocator {
+    /// The bitmap itself is stored in the first usable region large enough
+    /// to hold it, accessed through the physical memory mapping at
+    /// `physical_offset`.
+    ///
+    /// Unsafe because the caller must guarantee the memory map is accurate
+    /// and that only one allocator is ever built from it.
+    pub unsafe fn new(memory_map : &'static MemoryMap, physical_offset : VirtAddr) -> FrameAllocator {
+        let frame_count = memory_map.iter()
+            .filter(|r| is_usable(r))
+            .map(|r| r.range.end_frame_number)
+            .max()
+            .unwrap_or(0) as usize;
+
+        let words = (frame_count + 63) / 64;
+        let bitmap_frames = ((words as u64 * 8) + FRAME_SIZE - 1) / FRAME_SIZE;
+
+        let bitmap_region = memory_map.iter()
+            .filter(|r| is_usable(r) && r.range.start_addr() >= LOW_MEMORY_END)
+            .find(|r| r.range.end_frame_number - r.range.start_frame_number >= bitmap_frames)
+            .expect("No usable memory region can hold the frame bitmap");
+
+        let bitmap_start = bitmap_region.range.start_addr();
+        let bitmap_end = bitmap_start + bitmap_frames * FRAME_SIZE;
+        let bitmap_ptr : *mut u64 = (physical_offset + bitmap_start).as_mut_ptr();
+        let bitmap = core::slice::from_raw_parts_mut(bitmap_ptr, words);
+        for word in bitmap.iter_mut() {
+            *word = !0;
+        }
+
+        let mut allocator = FrameAllocator {
+            memory_map   : memory_map,
+            bitmap       : bitmap,
+            bitmap_start : bitmap_start,
+            bitmap_end   : bitmap_end,
+            total_frames : 0,
+            used_frames  : 0,
+            next_free    : 0
+        };
+
+        for region in memory_map.iter().filter(|r| is_usable(r)) {
+            for frame in region.range.start_frame_number..region.range.end_frame_number {
+                if frame * FRAME_SIZE < LOW_MEMORY_END { continue; }
+                allocator.clear_bit(frame as usize);
+                allocator.total_frames += 1;
+            }
+        }
+
+        for frame in (bitmap_start / FRAME_SIZE)..(bitmap_end / FRAME_SIZE) {
+            allocator.set_bit(frame as usize);
+            allocator.used_frames += 1;
+        }
+
+        allocator
+    }
+
+    pub fn allocate(&mut self) -> Option<PhysFrame> {
+        let words = self.bitmap.len();
+        for i in 0..words {
+            let index = (self.next_free + i) % words;
+            let word = self.bitmap[index];
+            if word != !0 {
+                let bit = (!word).trailing_zeros() as usize;
+                let frame = index * 64 + bit;
+                self.set_bit(frame);
+                self.used_frames += 1;
+                self.next_free = index;
+                return Some(frame_at(frame));
+            }
+        }
+        None
+    }
+
+    /// Returns a frame to the pool.
+    ///
+    /// Panics if the frame was never handed out by this allocator, which
+    /// covers double frees as well as attempts to free kernel or firmware memory.
+    pub fn deallocate(&mut self, frame : PhysFrame) {
+        let addr = frame.start_address().as_u64();
+        if !self.is_managed(addr) {
+            panic!("Frame at 0x{:x} is not managed by the frame allocator", addr);
+        }
+
+        let index = (addr / FRAME_SIZE) as usize;
+        if !self.is_set(index) {
+            panic!("Frame at 0x{:x} was freed twice", addr);
+        }
+
+        self.clear_bit(index);
+        self.used_frames -= 1;
+        if index / 64 < self.next_free {
+            self.next_free = index / 64;
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total : self.total_frames,
+            used  : self.used_frames,
+            free  : self.total_frames - self.used_frames
+        }
+    }
+
+    fn is_managed(&self, addr : u64) -> bool {
+        if addr < LOW_MEMORY_END { return false; }
+        if addr >= self.bitmap_start && addr < self.bitmap_end { return false; }
+        self.memory_map.iter()
+            .filter(|r| is_usable(r))
+            .any(|r| addr >= r.range.start_addr() && addr < r.range.end_addr())
+    }
+
+    fn is_set(&self, frame : usize) -> bool {
+        self.bitmap[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set_bit(&mut self, frame : usize) {
+        self.bitmap[frame / 64] |= 1 << (frame % 64);
+    }
+
+    fn clear_bit(&mut self, frame : usize) {
+        self.bitmap[frame / 64] &= !(1 << (frame % 64));
+    }
+}
+
+unsafe impl PagingFrameAllocator<Size4KiB> for FrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate()
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for FrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame : PhysFrame) {
+        self.deallocate(frame);
+    }
+}
+
+fn is_usable(region : &MemoryRegion) -> bool {
+    region.region_type == MemoryRegionType::Usable
+}
+
+fn frame_at(index : usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+}
@@ -0,0 +1,362 @@
+//mouse.rs
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::input::{self, InputEvent};
+use crate::irq;
+use crate::ps2::{self, Ps2Error, Ps2Port};
+use crate::serial;
+use crate::vga::{self, ColorCode, ScreenBuffer};
+
+pub static MOUSE_IRQ : u8 = 12;
+
+const SET_SAMPLE_RATE  : u8 = 0xF3;
+const IDENTIFY         : u8 = 0xF2;
+const ENABLE_REPORTING : u8 = 0xF4;
+const SET_DEFAULTS     : u8 = 0xF6;
+
+const WHEEL_MOUSE_ID       : u8 = 0x03;
+const FIVE_BUTTON_MOUSE_ID : u8 = 0x04;
+const SAMPLE_RATE          : u8 = 100;
+
+const BUTTON_LEFT   : u8 = 0b0000_0001;
+const BUTTON_RIGHT  : u8 = 0b0000_0010;
+const BUTTON_MIDDLE : u8 = 0b0000_0100;
+//Every first byte has this set, which is how a lost byte is noticed.
+const ALWAYS_ONE    : u8 = 0b0000_1000;
+const X_SIGN        : u8 = 0b0001_0000;
+const Y_SIGN        : u8 = 0b0010_0000;
+const X_OVERFLOW    : u8 = 0b0100_0000;
+const Y_OVERFLOW    : u8 = 0b1000_0000;
+
+/// Movement counts it takes to cross one character cell, rows being about
+/// twice as tall as columns are wide.
+const COUNTS_PER_COLUMN : i32 = 8;
+const COUNTS_PER_ROW    : i32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle
+}
+
+impl MouseButton {
+    fn mask(&self) -> u8 {
+        match self {
+            MouseButton::Left   => BUTTON_LEFT,
+            MouseButton::Right  => BUTTON_RIGHT,
+            MouseButton::Middle => BUTTON_MIDDLE
+        }
+    }
+}
+
+/// One decoded movement packet. `dy` is positive upwards, as the mouse
+/// reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub buttons : u8,
+    pub dx      : i16,
+    pub dy      : i16,
+    pub dz      : i8
+}
+
+impl Packet {
+    /// Decodes a 3 byte packet, or a 4 byte one from a wheel mouse.
+    pub fn parse(bytes : &[u8]) -> Option<Packet> {
+        let flags = *bytes.first()?;
+        if flags & ALWAYS_ONE == 0 || bytes.len() < 3 {
+            return None;
+        }
+
+        //The ninth bit of each delta lives in the flags byte.
+        let mut dx = bytes[1] as i16 - (((flags & X_SIGN) as i16) << 4);
+        let mut dy = bytes[2] as i16 - (((flags & Y_SIGN) as i16) << 3);
+        if flags & X_OVERFLOW != 0 { dx = 0; }
+        if flags & Y_OVERFLOW != 0 { dy = 0; }
+
+        //The wheel is a 4 bit two's complement value.
+        let dz = match bytes.get(3) {
+            Some(&z) => ((z << 4) as i8) >> 4,
+            None => 0
+        };
+
+        Some(Packet { buttons : flags & (BUTTON_LEFT | BUTTON_RIGHT | BUTTON_MIDDLE), dx, dy, dz })
+    }
+
+    pub fn is_pressed(&self, button : MouseButton) -> bool {
+        self.buttons & button.mask() != 0
+    }
+}
+
+struct Pointer {
+    x : usize,
+    y : usize,
+    //The cell's own colours, and the ones we left in it to tell whether
+    //something has been written over the pointer since.
+    under : (u8, u8),
+    drawn : (u8, u8)
+}
+
+struct Mouse {
+    packet      : [u8; 4],
+    received    : usize,
+    packet_size : usize,
+    //Position in movement counts, so slow movements still add up.
+    x           : i32,
+    y           : i32,
+    buttons     : u8,
+    show        : bool,
+    pointer     : Option<Pointer>
+}
+
+impl Mouse {
+    fn receive(&mut self, byte : u8) {
+        if self.received == 0 && byte & ALWAYS_ONE == 0 {
+            return;
+        }
+        self.packet[self.received] = byte;
+        self.received += 1;
+        if self.received < self.packet_size {
+            return;
+        }
+
+        self.received = 0;
+        if let Some(packet) = Packet::parse(&self.packet[..self.packet_size]) {
+            self.apply(packet);
+        }
+    }
+
+    fn apply(&mut self, packet : Packet) {
+        let (width, height) = vga::screen_dimensions();
+        self.x = (self.x + packet.dx as i32).clamp(0, width as i32 * COUNTS_PER_COLUMN - 1);
+        //The screen counts rows downwards.
+        self.y = (self.y - packet.dy as i32).clamp(0, height as i32 * COUNTS_PER_ROW - 1);
+        let (x, y) = self.position();
+
+        for &button in &[MouseButton::Left, MouseButton::Right, MouseButton::Middle] {
+            let pressed = packet.is_pressed(button);
+            if pressed != (self.buttons & button.mask() != 0) {
+                input::push_event(InputEvent::MouseButton { button, pressed, x, y });
+            }
+        }
+        self.buttons = packet.buttons;
+        if packet.dz != 0 {
+            input::push_event(InputEvent::MouseScroll { delta : packet.dz, x, y });
+        }
+
+        if self.show {
+            self.draw_pointer();
+        }
+    }
+
+    fn position(&self) -> (usize, usize) {
+        ((self.x / COUNTS_PER_COLUMN) as usize, (self.y / COUNTS_PER_ROW) as usize)
+    }
+
+    /// Swaps the colours of the cell under the pointer.
+    fn draw_pointer(&mut self) {
+        let (x, y) = self.position();
+        if let Some(pointer) = &self.pointer {
+            if pointer.x == x && pointer.y == y {
+                return;
+            }
+        }
+        self.erase_pointer();
+
+        //Swapping the colours keeps whatever is in the cell readable, though
+        //the pointer disappears over cells drawn in a single colour. The top
+        //background bit is the blink bit, so bright colours lose it.
+        let screen = ScreenBuffer::new();
+        let under = (screen.get_fg_color(x, y), screen.get_bg_color(x, y));
+        let drawn = (under.1, under.0 & 0x07);
+        screen.set_cell_attribs(x, y, ColorCode::from_u8s(drawn.0, drawn.1));
+        self.pointer = Some(Pointer { x, y, under, drawn });
+    }
+
+    fn erase_pointer(&mut self) {
+        if let Some(pointer) = self.pointer.take() {
+            let screen = ScreenBuffer::new();
+            let (x, y) = (pointer.x, pointer.y);
+            //Leave the cell alone if it has been redrawn under the pointer.
+            if (screen.get_fg_color(x, y), screen.get_bg_color(x, y)) == pointer.drawn {
+                screen.set_cell_attribs(x, y, ColorCode::from_u8s(pointer.under.0, pointer.under.1));
+            }
+        }
+    }
+}
+
+static MOUSE : Mutex<Option<Mouse>> = Mutex::new(None);
+
+/// Sets up the mouse on the second PS/2 port, if `ps2::init` found one.
+/// Polls for the mouse's responses, so it must run before interrupts are
+/// enabled.
+pub fn init() {
+    let detected = ps2::controller()
+        .and_then(|controller| controller.second)
+        .map_or(false, |device| device.is_mouse());
+    if !detected {
+        return;
+    }
+
+    serial::print!("Initialising Mouse...");
+    let packet_size = match configure() {
+        Ok(packet_size) => packet_size,
+        Err(error) => {
+            serial::println!("[FAILED] {:?}", error);
+            return;
+        }
+    };
+
+    if let Err(error) = irq::register(MOUSE_IRQ, handle_interrupt) {
+        serial::println!("[FAILED] {:?}", error);
+        return;
+    }
+    let (width, height) = vga::screen_dimensions();
+    interrupts::without_interrupts(|| {
+        *MOUSE.lock() = Some(Mouse {
+            packet      : [0; 4],
+            received    : 0,
+            packet_size,
+            x           : width as i32 * COUNTS_PER_COLUMN / 2,
+            y           : height as i32 * COUNTS_PER_ROW / 2,
+            buttons     : 0,
+            show        : true,
+            pointer     : None
+        });
+    });
+    serial::println!("[OK] {}", if packet_size == 4 { "with scroll wheel" } else { "no scroll wheel" });
+}
+
+/// Returns the size of the packets the mouse will send.
+fn configure() -> Result<usize, Ps2Error> {
+    ps2::send(Ps2Port::Second, SET_DEFAULTS)?;
+    //The IntelliMouse knock, a wheel mouse only reports its wheel after this.
+    for &rate in &[200, 100, 80] {
+        set_sample_rate(rate)?;
+    }
+    ps2::send(Ps2Port::Second, IDENTIFY)?;
+    let id = ps2::read_data()?;
+    set_sample_rate(SAMPLE_RATE)?;
+    ps2::send(Ps2Port::Second, ENABLE_REPORTING)?;
+
+    Ok(match id {
+        WHEEL_MOUSE_ID | FIVE_BUTTON_MOUSE_ID => 4,
+        _ => 3
+    })
+}
+
+fn set_sample_rate(rate : u8) -> Result<(), Ps2Error> {
+    ps2::send(Ps2Port::Second, SET_SAMPLE_RATE)?;
+    ps2::send(Ps2Port::Second, rate)
+}
+
+fn handle_interrupt() {
+    //Ignore an interrupt left pending from the polled setup, and leave
+    //keyboard bytes for the keyboard's handler.
+    let status = ps2::read_status();
+    if status & ps2::STATUS_OUTPUT_FULL == 0 || status & ps2::STATUS_AUX_DATA == 0 {
+        return;
+    }
+    let byte : u8 = unsafe { Port::new(ps2::DATA_PORT).read() };
+    receive(byte);
+}
+
+/// Takes a byte the keyboard driver read while polling with interrupts off.
+pub(crate) fn receive(byte : u8) {
+    interrupts::without_interrupts(|| {
+        if let Some(mouse) = MOUSE.lock().as_mut() {
+            mouse.receive(byte);
+        }
+    });
+}
+
+pub fn is_present() -> bool {
+    interrupts::without_interrupts(|| MOUSE.lock().is_some())
+}
+
+/// The character cell under the pointer.
+pub fn position() -> Option<(usize, usize)> {
+    interrupts::without_interrupts(|| MOUSE.lock().as_ref().map(|mouse| mouse.position()))
+}
+
+pub fn is_pressed(button : MouseButton) -> bool {
+    interrupts::without_interrupts(|| {
+        MOUSE.lock().as_ref().map_or(false, |mouse| mouse.buttons & button.mask() != 0)
+    })
+}
+
+pub fn show_pointer() {
+    interrupts::without_interrupts(|| {
+        if let Some(mouse) = MOUSE.lock().as_mut() {
+            mouse.show = true;
+            mouse.draw_pointer();
+        }
+    });
+}
+
+pub fn hide_pointer() {
+    interrupts::without_interrupts(|| {
+        if let Some(mouse) = MOUSE.lock().as_mut() {
+            mouse.show = false;
+            mouse.erase_pointer();
+        }
+    });
+}
+
+#[test_case]
+fn test_parse_packet() {
+    //Left button, moved right 5 and down 3.
+    let packet = Packet::parse(&[0b0010_1001, 5, 0xFD]).unwrap();
+    assert!(packet.is_pressed(MouseButton::Left));
+    assert!(!packet.is_pressed(MouseButton::Right));
+    assert_eq!((packet.dx, packet.dy, packet.dz), (5, -3, 0));
+
+    //Negative x and a wheel click towards the user.
+    let packet = Packet::parse(&[0b0001_1000, 0xF0, 0, 0x0F]).unwrap();
+    assert_eq!((packet.dx, packet.dy, packet.dz), (-16, 0, -1));
+}
+
+#[test_case]
+fn test_parse_rejects_bad_packets() {
+    assert_eq!(Packet::parse(&[0b0000_0001, 5, 5]), None);
+    assert_eq!(Packet::parse(&[0b0000_1000, 5]), None);
+    //Overflowed deltas are thrown away.
+    let packet = Packet::parse(&[0b1100_1000, 0xFF, 0xFF]).unwrap();
+    assert_eq!((packet.dx, packet.dy), (0, 0));
+}
+
+#[test_case]
+fn test_movement_is_clamped_and_buttons_reported() {
+    let mut mouse = Mouse {
+        packet      : [0; 4],
+        received    : 0,
+        packet_size : 3,
+        x           : 0,
+        y           : 0,
+        buttons     : 0,
+        show        : false,
+        pointer     : None
+    };
+    while input::try_read_event().is_some() {}
+
+    //Left and up, past the corner, with the left button down.
+    for &byte in &[0b0001_1001, 0x80, 0x7F] {
+        mouse.receive(byte);
+    }
+    assert_eq!(mouse.position(), (0, 0));
+    assert_eq!(input::try_read_event(),
+        Some(InputEvent::MouseButton { button : MouseButton::Left, pressed : true, x : 0, y : 0 }));
+
+    //A byte without the always set bit can't start a packet. Then right and
+    //down with the button released.
+    mouse.receive(0);
+    for &byte in &[0b0010_1000, 0x7F, 0x80] {
+        mouse.receive(byte);
+    }
+    assert_eq!(mouse.position(), (127 / COUNTS_PER_COLUMN as usize, 128 / COUNTS_PER_ROW as usize));
+    assert_eq!(input::try_read_event(),
+        Some(InputEvent::MouseButton { button : MouseButton::Left, pressed : false, x : 15, y : 8 }));
+}
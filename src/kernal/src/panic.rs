@@ -0,0 +1,119 @@
+//panic.rs
+
+use core::fmt;
+use core::panic::PanicInfo;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::rflags;
+
+use crate::backtrace::Backtrace;
+use crate::serial;
+use crate::terminal;
+use crate::vga::Color;
+
+/// Snapshot of the general purpose and control registers, taken as close to
+/// the panic as we can get from inside the handler.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Registers {
+    pub rax : u64, pub rbx : u64, pub rcx : u64, pub rdx : u64,
+    pub rsi : u64, pub rdi : u64, pub rbp : u64, pub rsp : u64,
+    pub r8  : u64, pub r9  : u64, pub r10 : u64, pub r11 : u64,
+    pub r12 : u64, pub r13 : u64, pub r14 : u64, pub r15 : u64,
+    pub rip : u64, pub rflags : u64,
+    pub cr0 : u64, pub cr2 : u64, pub cr3 : u64, pub cr4 : u64
+}
+
+impl Registers {
+    #[inline(always)]
+    pub fn capture() -> Registers {
+        //One mov per asm block, so an output register picked for one value
+        //can never be a register another mov still has to read from.
+        macro_rules! read {
+            ($mov:literal) => {{
+                let value : u64;
+                unsafe {
+                    asm!($mov, out(reg) value, options(nomem, nostack, preserves_flags));
+                }
+                value
+            }};
+        }
+
+        Registers {
+            rax : read!("mov {}, rax"), rbx : read!("mov {}, rbx"),
+            rcx : read!("mov {}, rcx"), rdx : read!("mov {}, rdx"),
+            rsi : read!("mov {}, rsi"), rdi : read!("mov {}, rdi"),
+            rbp : read!("mov {}, rbp"), rsp : read!("mov {}, rsp"),
+            r8  : read!("mov {}, r8"),  r9  : read!("mov {}, r9"),
+            r10 : read!("mov {}, r10"), r11 : read!("mov {}, r11"),
+            r12 : read!("mov {}, r12"), r13 : read!("mov {}, r13"),
+            r14 : read!("mov {}, r14"), r15 : read!("mov {}, r15"),
+            rip : read!("lea {}, [rip]"),
+            rflags : rflags::read_raw(),
+            cr0 : Cr0::read_raw(),
+            cr2 : Cr2::read().as_u64(),
+            cr3 : Cr3::read().0.start_address().as_u64(),
+            cr4 : Cr4::read_raw()
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let rows : [[(&str, u64); 3]; 8] = [
+            [("RAX", self.rax), ("RBX", self.rbx), ("RCX", self.rcx)],
+            [("RDX", self.rdx), ("RSI", self.rsi), ("RDI", self.rdi)],
+            [("RBP", self.rbp), ("RSP", self.rsp), ("R8 ", self.r8)],
+            [("R9 ", self.r9),  ("R10", self.r10), ("R11", self.r11)],
+            [("R12", self.r12), ("R13", self.r13), ("R14", self.r14)],
+            [("R15", self.r15), ("RIP", self.rip), ("RFL", self.rflags)],
+            [("CR0", self.cr0), ("CR2", self.cr2), ("CR3", self.cr3)],
+            [("CR4", self.cr4), ("", 0), ("", 0)]
+        ];
+
+        for row in rows.iter() {
+            for &(name, value) in row.iter().filter(|(name, _)| !name.is_empty()) {
+                write!(f, "{}={:016x}  ", name, value)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Kernel panic routine: paints the panic screen and mirrors the report to
+/// COM1 so headless runs capture it, then halts.
+pub fn handle_panic(info : &PanicInfo) -> ! {
+    crate::disable_interrupts();
+    let registers = Registers::capture();
+    let backtrace = Backtrace::capture();
+
+    //Whoever panicked may well have been holding one of these.
+    unsafe {
+        serial::SERIAL1.force_unlock();
+        terminal::force_unlock();
+    }
+
+    terminal::set_background!(Color::Red as u8);
+    terminal::clear!();
+    terminal::set_position!(0,0);
+
+    emit(format_args!("KERNEL PANIC"));
+    match info.message() {
+        Some(message) => emit(format_args!("{}", message)),
+        None => emit(format_args!("<no message>"))
+    }
+    match info.location() {
+        Some(location) => emit(format_args!("at {}:{}:{}",
+            location.file(), location.line(), location.column())),
+        None => emit(format_args!("at <unknown location>"))
+    }
+    emit(format_args!(""));
+    emit(format_args!("{}", registers));
+    emit(format_args!("{}", backtrace));
+
+    crate::spin!();
+}
+
+fn emit(args : fmt::Arguments) {
+    serial::println!("{}", args);
+    terminal::println!("{}", args);
+}
@@ -1,20 +1,66 @@
 use pic8259_simple::ChainedPics;
 use spin;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::apic;
 
 pub const PIC_1_OFFSET : u8 = 32;
 pub const PIC_2_OFFSET : u8 = PIC_1_OFFSET + 8;
 
+pub static PIC_1_COMMAND : u16 = 0x20;
+pub static PIC_1_DATA    : u16 = 0x21;
+pub static PIC_2_COMMAND : u16 = 0xA0;
+pub static PIC_2_DATA    : u16 = 0xA1;
+
+static OCW3_READ_ISR : u8 = 0x0B;
+static EOI           : u8 = 0x20;
+
+//The secondary PIC is chained into this line of the primary.
+pub static CASCADE_IRQ : u8 = 2;
+
 pub static PICS : spin::Mutex<ChainedPics> = 
     spin::Mutex::new( unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
-#[derive(Debug, Clone, Copy)]
+/// Vectors of the sixteen ISA IRQ lines, named after what is usually
+/// wired to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
-    Keyboard
+    Keyboard,
+    Cascade,
+    Com2,
+    Com1,
+    Lpt2,
+    Floppy,
+    Lpt1,
+    Rtc = PIC_2_OFFSET,
+    Acpi,
+    Free10,
+    Free11,
+    Mouse,
+    Fpu,
+    PrimaryAta,
+    SecondaryAta
 }
 
+static INTERRUPT_INDICES : [InterruptIndex; 16] = [
+    InterruptIndex::Timer, InterruptIndex::Keyboard, InterruptIndex::Cascade, InterruptIndex::Com2,
+    InterruptIndex::Com1, InterruptIndex::Lpt2, InterruptIndex::Floppy, InterruptIndex::Lpt1,
+    InterruptIndex::Rtc, InterruptIndex::Acpi, InterruptIndex::Free10, InterruptIndex::Free11,
+    InterruptIndex::Mouse, InterruptIndex::Fpu, InterruptIndex::PrimaryAta, InterruptIndex::SecondaryAta
+];
+
 impl InterruptIndex {
+    pub fn from_irq(irq : u8) -> Option<InterruptIndex> {
+        INTERRUPT_INDICES.get(irq as usize).copied()
+    }
+
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+
     pub fn as_u8(self) -> u8 {
         self as u8
     }
@@ -24,9 +70,68 @@ impl InterruptIndex {
     }
 }
 
+/// Acknowledges `index` with whichever interrupt controller is in charge.
 pub fn clear_interrupt(index : InterruptIndex) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+        return;
+    }
     unsafe {
         PICS.lock().notify_end_of_interrupt(index.as_u8());
     }
 }
 
+/// IRQs 7 and 15 are also what the PICs raise for an interrupt that went
+/// away before it was acknowledged. Those have no bit set in the in-service
+/// register and must not get a normal EOI.
+pub fn is_spurious(irq : u8) -> bool {
+    if apic::is_enabled() || (irq != 7 && irq != 15) {
+        return false;
+    }
+    let (command, line) = if irq < 8 { (PIC_1_COMMAND, irq) } else { (PIC_2_COMMAND, irq - 8) };
+    without_interrupts(|| unsafe {
+        let mut port : Port<u8> = Port::new(command);
+        port.write(OCW3_READ_ISR);
+        port.read() & (1 << line) == 0
+    })
+}
+
+/// Finishes a spurious IRQ. The secondary PIC's still went through the
+/// cascade line, so the primary needs its EOI.
+pub fn end_spurious_interrupt(irq : u8) {
+    if irq >= 8 {
+        unsafe { Port::<u8>::new(PIC_1_COMMAND).write(EOI); }
+    }
+}
+
+/// Lets `irq` (0-15) through to the CPU, through the I/O APIC when it has
+/// taken over. Lines on the secondary PIC also need the cascade line open.
+pub fn unmask(irq : u8) {
+    if apic::is_enabled() {
+        apic::set_irq_masked(irq, false);
+        return;
+    }
+    if irq >= 8 {
+        set_masked(CASCADE_IRQ, false);
+    }
+    set_masked(irq, false);
+}
+
+pub fn mask(irq : u8) {
+    if apic::is_enabled() {
+        apic::set_irq_masked(irq, true);
+        return;
+    }
+    set_masked(irq, true);
+}
+
+fn set_masked(irq : u8, masked : bool) {
+    let (port, line) = if irq < 8 { (PIC_1_DATA, irq) } else { (PIC_2_DATA, irq - 8) };
+    without_interrupts(|| {
+        let mut port : Port<u8> = Port::new(port);
+        unsafe {
+            let mask = port.read();
+            port.write(if masked { mask | (1 << line) } else { mask & !(1 << line) });
+        }
+    });
+}
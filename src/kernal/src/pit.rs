@@ -1,20 +1,74 @@
 use x86_64::instructions::port::Port;
 use x86_64::instructions::interrupts::without_interrupts;
-use crate::terminal;
 
-pub unsafe fn set_reload_value(mut value : u16) {
-    
-    //terminal::println!("Setting PIT Reload Value to {}", value);
+pub unsafe fn set_reload_value(value : u16) {
+    program(CHANNEL_0, DATA_PORT_0, MODE_3, value);
+}
 
+/// Sets `channel` to `mode` and loads `value`, low byte first. A value of 0
+/// counts 65536 cycles.
+pub unsafe fn program(channel : u8, data_port : u16, mode : u8, value : u16) {
     without_interrupts( || {
-        let mut data_port = Port::new(DATA_PORT_0);
-        let mut command_port = Port::new(COMMAND_PORT);
-        command_port.write(CHANNEL_0 | ACCESS_LOBYTE_HIBYTE | MODE_3);
-        data_port.write(value & 0x00FF);
-        data_port.write((value & 0xFF00) >> 8);
+        let mut data_port : Port<u8> = Port::new(data_port);
+        let mut command_port : Port<u8> = Port::new(COMMAND_PORT);
+        command_port.write(channel | ACCESS_LOBYTE_HIBYTE | mode);
+        data_port.write((value & 0x00FF) as u8);
+        data_port.write(((value & 0xFF00) >> 8) as u8);
     }); 
 }
 
+/// Reload value closest to `hertz`, clamped to what the counter can hold.
+pub fn reload_for(hertz : u32) -> u16 {
+    let reload = FREQUENCY as u32 / hertz.max(1);
+    reload.max(1).min(0xFFFF) as u16
+}
+
+/// Starts channel 2 counting `cycles` down once, in mode 0, with the
+/// speaker disconnected. Poll `countdown_finished` for the end.
+pub fn start_countdown(cycles : u16) {
+    without_interrupts(|| unsafe {
+        let mut control : Port<u8> = Port::new(CHANNEL_2_CONTROL_PORT);
+        //Gate off while the count is loaded, so it starts when we say.
+        control.write(control.read() & !(CHANNEL_2_GATE | CHANNEL_2_SPEAKER));
+        program(CHANNEL_2, DATA_PORT_2, MODE_0, cycles);
+        while read_back_status_2() & STATUS_NULL_COUNT != 0 {}
+        control.write(control.read() | CHANNEL_2_GATE);
+    });
+}
+
+/// Mode 0 raises the channel's output when the count reaches zero.
+pub fn countdown_finished() -> bool {
+    read_back_status_2() & STATUS_OUTPUT != 0
+}
+
+pub fn stop_countdown() {
+    without_interrupts(|| unsafe {
+        let mut control : Port<u8> = Port::new(CHANNEL_2_CONTROL_PORT);
+        control.write(control.read() & !(CHANNEL_2_GATE | CHANNEL_2_SPEAKER));
+    });
+}
+
+/// Current channel 2 count, latched so both bytes are from the same moment.
+pub fn latch_channel_2() -> u16 {
+    without_interrupts(|| unsafe {
+        let mut command : Port<u8> = Port::new(COMMAND_PORT);
+        let mut data : Port<u8> = Port::new(DATA_PORT_2);
+        command.write(CHANNEL_2 | LATCH_COUNT_VALUE);
+        let low = data.read() as u16;
+        let high = data.read() as u16;
+        (high << 8) | low
+    })
+}
+
+fn read_back_status_2() -> u8 {
+    without_interrupts(|| unsafe {
+        let mut command : Port<u8> = Port::new(COMMAND_PORT);
+        let mut data : Port<u8> = Port::new(DATA_PORT_2);
+        command.write(READ_BACK | READ_BACK_NO_COUNT | READ_BACK_CHANNEL_2);
+        data.read()
+    })
+}
+
 //Runs at 1.193182MHz
 pub static FREQUENCY : usize = 1_193_182;
 
@@ -23,6 +77,11 @@ pub static DATA_PORT_1  : u16 = 0x0041;
 pub static DATA_PORT_2  : u16 = 0x0042;
 pub static COMMAND_PORT : u16 = 0x0043;
 
+//System control port B, which gates channel 2 and connects it to the speaker.
+pub static CHANNEL_2_CONTROL_PORT : u16 = 0x0061;
+pub static CHANNEL_2_GATE         : u8 = 0b01;
+pub static CHANNEL_2_SPEAKER      : u8 = 0b10;
+
 
 pub static CHANNEL_0 : u8 = 0b00000000;
 pub static CHANNEL_1 : u8 = 0b01000000;
@@ -40,3 +99,14 @@ pub static MODE_2 : u8 = 0b0100;
 pub static MODE_3 : u8 = 0b0110;
 pub static MODE_4 : u8 = 0b1000;
 pub static MODE_5 : u8 = 0b1010;
+
+//Read-back command bits, a 0 selects what to latch.
+pub static READ_BACK_NO_COUNT  : u8 = 0b00100000;
+pub static READ_BACK_NO_STATUS : u8 = 0b00010000;
+pub static READ_BACK_CHANNEL_0 : u8 = 0b00000010;
+pub static READ_BACK_CHANNEL_1 : u8 = 0b00000100;
+pub static READ_BACK_CHANNEL_2 : u8 = 0b00001000;
+
+//Read-back status byte.
+pub static STATUS_OUTPUT     : u8 = 0b10000000;
+pub static STATUS_NULL_COUNT : u8 = 0b01000000;
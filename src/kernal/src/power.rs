@@ -0,0 +1,232 @@
+//power.rs
+
+use x86_64::PhysAddr;
+use x86_64::instructions::port::Port;
+
+use crate::acpi::{self, AddressSpace, GenericAddress};
+use crate::memory;
+use crate::serial;
+use crate::testing;
+use crate::pit;
+
+//8042 controller
+static PS2_STATUS_PORT       : u16 = 0x64;
+static PS2_COMMAND_PORT      : u16 = 0x64;
+static PS2_STATUS_INPUT_FULL : u8 = 0b10;
+static PS2_PULSE_RESET       : u8 = 0xFE;
+
+static PCI_CONFIG_ADDRESS : u16 = 0xCF8;
+static PCI_CONFIG_DATA    : u16 = 0xCFC;
+
+//PM1 control register bits
+static PM1_SCI_EN        : u16 = 1 << 0;
+static PM1_SLP_TYP_SHIFT : u16 = 10;
+static PM1_SLP_EN        : u16 = 1 << 13;
+
+//AML opcodes needed to find \_S5 in the DSDT.
+static AML_NAME_OP     : u8 = 0x08;
+static AML_PACKAGE_OP  : u8 = 0x12;
+static AML_ZERO_OP     : u8 = 0x00;
+static AML_ONE_OP      : u8 = 0x01;
+static AML_BYTE_PREFIX : u8 = 0x0A;
+
+//Emulator specific power off ports, (port, value)
+static EMULATOR_SHUTDOWN : [(u16, u16); 3] = [
+    (0x604, 0x2000),  //QEMU
+    (0xB004, 0x2000), //Bochs and older QEMU
+    (0x4004, 0x3400)  //VirtualBox
+];
+
+/// How long each method gets to take effect before trying the next.
+static METHOD_TIMEOUT_MS : u64 = 100;
+
+/// Restarts the machine: through the ACPI reset register, then the 8042
+/// reset line, and finally by triple faulting.
+pub fn reboot() -> ! {
+    crate::disable_interrupts();
+    serial::println!("Rebooting...");
+
+    let fadt = acpi::tables().and_then(|t| t.fadt.as_ref());
+    if let Some(fadt) = fadt {
+        if let Some(register) = fadt.reset_register {
+            write_register(register, fadt.reset_value);
+            wait(METHOD_TIMEOUT_MS);
+        }
+    }
+
+    if fadt.map_or(true, |fadt| fadt.has_8042()) {
+        pulse_8042_reset();
+        wait(METHOD_TIMEOUT_MS);
+    }
+
+    serial::println!("Reset failed, triple faulting");
+    triple_fault();
+}
+
+/// Gives up if the controller never empties its input buffer, a missing or
+/// wedged 8042 shouldn't stop us getting to the triple fault.
+fn pulse_8042_reset() {
+    let mut status : Port<u8> = Port::new(PS2_STATUS_PORT);
+    let mut command : Port<u8> = Port::new(PS2_COMMAND_PORT);
+    unsafe {
+        let mut waited = 0;
+        while status.read() & PS2_STATUS_INPUT_FULL != 0 {
+            if waited == METHOD_TIMEOUT_MS {
+                return;
+            }
+            wait(1);
+            waited += 1;
+        }
+        command.write(PS2_PULSE_RESET);
+    }
+}
+
+/// Turns the machine off through ACPI S5, falling back to the power off
+/// ports of common emulators. Halts if all of them fail.
+pub fn shutdown() -> ! {
+    crate::disable_interrupts();
+    serial::println!("Shutting down...");
+
+    if let Err(reason) = acpi_shutdown() {
+        serial::println!("ACPI shutdown unavailable: {}", reason);
+    }
+
+    for &(port, value) in EMULATOR_SHUTDOWN.iter() {
+        unsafe { Port::<u16>::new(port).write(value); }
+    }
+    //Under the test runner's isa-debug-exit device this still ends QEMU.
+    unsafe { Port::<u32>::new(testing::ISA_DEBUG_EXIT_PORT).write(0); }
+
+    serial::println!("It is now safe to turn off your computer");
+    crate::spin!();
+}
+
+fn acpi_shutdown() -> Result<(), &'static str> {
+    let fadt = acpi::tables().and_then(|t| t.fadt.as_ref()).ok_or("no FADT")?;
+    if fadt.pm1a_control_block == 0 {
+        return Err("no PM1a control block");
+    }
+    let dsdt = acpi::dsdt().ok_or("no DSDT")?;
+    let (slp_typ_a, slp_typ_b) = find_s5(dsdt.data()).ok_or("no \\_S5 object")?;
+
+    let mut pm1a : Port<u16> = Port::new(fadt.pm1a_control_block as u16);
+    unsafe {
+        //Hand the hardware over from SMM to ACPI if the firmware hasn't yet.
+        if pm1a.read() & PM1_SCI_EN == 0 && fadt.smi_command_port != 0 && fadt.acpi_enable != 0 {
+            Port::<u8>::new(fadt.smi_command_port as u16).write(fadt.acpi_enable);
+            let mut waited = 0;
+            while pm1a.read() & PM1_SCI_EN == 0 {
+                if waited == METHOD_TIMEOUT_MS * 10 {
+                    return Err("firmware did not enable ACPI");
+                }
+                wait(1);
+                waited += 1;
+            }
+        }
+
+        pm1a.write((slp_typ_a as u16) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN);
+        if fadt.pm1b_control_block != 0 {
+            Port::<u16>::new(fadt.pm1b_control_block as u16)
+                .write((slp_typ_b as u16) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN);
+        }
+    }
+    wait(METHOD_TIMEOUT_MS);
+    Err("still running after entering S5")
+}
+
+/// Finds the SLP_TYPa and SLP_TYPb values of `Name (_S5, Package () {...})`
+/// in raw AML, without interpreting anything else.
+fn find_s5(aml : &[u8]) -> Option<(u8, u8)> {
+    let start = aml.windows(4).position(|w| w == b"_S5_")?;
+    //Either `NameOp _S5_` or `NameOp \_S5_`.
+    let named = (start >= 1 && aml[start - 1] == AML_NAME_OP)
+        || (start >= 2 && aml[start - 2] == AML_NAME_OP && aml[start - 1] == b'\\');
+    if !named {
+        return None;
+    }
+
+    let mut rest = aml.get(start + 4..)?;
+    if *rest.first()? != AML_PACKAGE_OP {
+        return None;
+    }
+    //The top two bits of PkgLength's lead byte count the extra bytes.
+    let length_bytes = (rest.get(1)? >> 6) as usize + 1;
+    //Skip PackageOp, PkgLength and NumElements.
+    rest = rest.get(1 + length_bytes + 1..)?;
+
+    let (a, rest) = aml_integer(rest)?;
+    let (b, _) = aml_integer(rest)?;
+    Some((a, b))
+}
+
+fn aml_integer(aml : &[u8]) -> Option<(u8, &[u8])> {
+    match *aml.first()? {
+        op if op == AML_ZERO_OP => Some((0, &aml[1..])),
+        op if op == AML_ONE_OP => Some((1, &aml[1..])),
+        op if op == AML_BYTE_PREFIX => Some((*aml.get(1)?, aml.get(2..)?)),
+        _ => None
+    }
+}
+
+fn write_register(register : GenericAddress, value : u8) {
+    match register.space {
+        AddressSpace::SystemIo => unsafe {
+            Port::<u8>::new(register.address as u16).write(value);
+        },
+        AddressSpace::SystemMemory => {
+            if let Ok(virt) = memory::map_mmio(PhysAddr::new(register.address), 1) {
+                unsafe { core::ptr::write_volatile(virt.as_mut_ptr::<u8>(), value); }
+            }
+        }
+        AddressSpace::PciConfig => {
+            //Device and function are packed above the register offset.
+            let device = (register.address >> 32) as u32 & 0xFFFF;
+            let function = (register.address >> 16) as u32 & 0xFFFF;
+            let offset = register.address as u32 & 0xFFFF;
+            let address = 1 << 31 | device << 11 | function << 8 | (offset & 0xFC);
+            unsafe {
+                Port::<u32>::new(PCI_CONFIG_ADDRESS).write(address);
+                Port::<u8>::new(PCI_CONFIG_DATA + (offset & 0b11) as u16).write(value);
+            }
+        }
+        AddressSpace::Other(_) => {}
+    }
+}
+
+/// Busy waits on PIT channel 2, interrupts are off by the time we get here
+/// so the tick count has stopped.
+fn wait(ms : u64) {
+    let cycles_per_ms = pit::reload_for(1000);
+    for _ in 0..ms {
+        pit::start_countdown(cycles_per_ms);
+        while !pit::countdown_finished() {}
+    }
+    pit::stop_countdown();
+}
+
+/// Loads an empty IDT so the next exception can't be delivered, which the
+/// CPU escalates into a reset.
+fn triple_fault() -> ! {
+    #[repr(C, packed)]
+    struct IdtPointer {
+        limit : u16,
+        base  : u64
+    }
+    let pointer = IdtPointer { limit : 0, base : 0 };
+    unsafe {
+        asm!("lidt [{}]", "int3", in(reg) &pointer, options(noreturn));
+    }
+}
+
+#[test_case]
+fn test_find_s5() {
+    //Name (_S5, Package (0x04) { 0x05, Zero, Zero, Zero })
+    let aml = [0x10, 0x08, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0A, 0x05, 0x00, 0x00, 0x00];
+    assert_eq!(find_s5(&aml), Some((5, 0)));
+
+    //Name (\_S5, Package (0x02) { One, 0x07 })
+    let aml = [0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x06, 0x02, 0x01, 0x0A, 0x07];
+    assert_eq!(find_s5(&aml), Some((1, 7)));
+
+    assert_eq!(find_s5(b"no sleep states here"), None);
+}
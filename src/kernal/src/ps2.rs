@@ -0,0 +1,405 @@
+//ps2.rs
+
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::acpi;
+use crate::keyboard::{self, ScancodeSet};
+use crate::serial;
+use crate::tsc;
+
+pub static DATA_PORT    : u16 = 0x60;
+pub static STATUS_PORT  : u16 = 0x64;
+pub static COMMAND_PORT : u16 = 0x64;
+
+pub static STATUS_OUTPUT_FULL : u8 = 0b0000_0001;
+pub static STATUS_INPUT_FULL  : u8 = 0b0000_0010;
+/// Set when the byte in the output buffer came from the second port.
+pub static STATUS_AUX_DATA    : u8 = 0b0010_0000;
+
+/// How long the controller or a device gets to answer a command.
+pub const RESPONSE_TIMEOUT : Duration = Duration::from_millis(20);
+//Devices run a self test after a reset, which is a lot slower.
+const RESET_TIMEOUT : Duration = Duration::from_millis(500);
+//Mice send their ID straight after the self test result, keyboards nothing.
+const RESET_ID_TIMEOUT : Duration = Duration::from_millis(10);
+const RESEND_RETRIES : usize = 3;
+//The output buffer is one byte, but devices may have more queued behind it.
+const FLUSH_LIMIT : usize = 64;
+
+const READ_CONFIG    : u8 = 0x20;
+const WRITE_CONFIG   : u8 = 0x60;
+const DISABLE_SECOND : u8 = 0xA7;
+const ENABLE_SECOND  : u8 = 0xA8;
+const TEST_SECOND    : u8 = 0xA9;
+const SELF_TEST      : u8 = 0xAA;
+const TEST_FIRST     : u8 = 0xAB;
+const DISABLE_FIRST  : u8 = 0xAD;
+const ENABLE_FIRST   : u8 = 0xAE;
+const WRITE_SECOND   : u8 = 0xD4;
+
+const SELF_TEST_PASSED : u8 = 0x55;
+const PORT_TEST_PASSED : u8 = 0x00;
+
+const CONFIG_FIRST_IRQ        : u8 = 0b0000_0001;
+const CONFIG_SECOND_IRQ       : u8 = 0b0000_0010;
+const CONFIG_FIRST_CLOCK_OFF  : u8 = 0b0001_0000;
+const CONFIG_SECOND_CLOCK_OFF : u8 = 0b0010_0000;
+const CONFIG_TRANSLATION      : u8 = 0b0100_0000;
+
+const DEVICE_SCANCODE_SET     : u8 = 0xF0;
+const DEVICE_IDENTIFY         : u8 = 0xF2;
+const DEVICE_ENABLE_SCANNING  : u8 = 0xF4;
+const DEVICE_DISABLE_SCANNING : u8 = 0xF5;
+const DEVICE_RESET            : u8 = 0xFF;
+
+const DEVICE_SELF_TEST_PASSED : u8 = 0xAA;
+const DEVICE_ACK              : u8 = 0xFA;
+const DEVICE_RESEND           : u8 = 0xFE;
+
+/// 20 keys a second after a 500 ms delay.
+pub static DEFAULT_TYPEMATIC_RATE  : u8 = 0x04;
+pub static DEFAULT_TYPEMATIC_DELAY : u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    First,
+    Second
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    /// An AT keyboard, too old to answer the identify command.
+    AtKeyboard,
+    Mf2Keyboard,
+    /// An MF2 keyboard identifying itself through the controller's translation.
+    Mf2KeyboardTranslated,
+    Mouse,
+    WheelMouse,
+    FiveButtonMouse,
+    Unknown(u8, u8)
+}
+
+impl DeviceType {
+    fn from_id(id : &[u8]) -> DeviceType {
+        match id {
+            []                         => DeviceType::AtKeyboard,
+            [0x00]                     => DeviceType::Mouse,
+            [0x03]                     => DeviceType::WheelMouse,
+            [0x04]                     => DeviceType::FiveButtonMouse,
+            [0xAB, 0x83]               => DeviceType::Mf2Keyboard,
+            [0xAB, 0x41] | [0xAB, 0xC1] => DeviceType::Mf2KeyboardTranslated,
+            [first]                    => DeviceType::Unknown(*first, 0),
+            [first, second, ..]        => DeviceType::Unknown(*first, *second)
+        }
+    }
+
+    pub fn is_keyboard(&self) -> bool {
+        match self {
+            DeviceType::AtKeyboard | DeviceType::Mf2Keyboard | DeviceType::Mf2KeyboardTranslated => true,
+            _ => false
+        }
+    }
+
+    pub fn is_mouse(&self) -> bool {
+        match self {
+            DeviceType::Mouse | DeviceType::WheelMouse | DeviceType::FiveButtonMouse => true,
+            _ => false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// ACPI says there is no 8042.
+    NoController,
+    Timeout,
+    SelfTestFailed(u8),
+    PortTestFailed(Ps2Port, u8),
+    /// The device kept asking for a byte to be resent.
+    Resend,
+    UnexpectedResponse(u8)
+}
+
+/// What `init` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Controller {
+    pub dual_channel : bool,
+    pub first        : Option<DeviceType>,
+    pub second       : Option<DeviceType>,
+    /// The controller turns the keyboard's set 2 scancodes into set 1.
+    pub translation  : bool
+}
+
+static CONTROLLER : Mutex<Option<Controller>> = Mutex::new(None);
+
+/// Resets and configures the controller and whatever is plugged into it.
+/// Polls for every response, so it must run with interrupts disabled and
+/// after `tsc::init`, which the timeouts are measured with.
+pub fn init() {
+    serial::print!("Initialising PS/2 Controller...");
+    match initialise() {
+        Ok(controller) => {
+            serial::println!("[OK] first port: {:?}, second port: {:?}{}",
+                controller.first,
+                controller.second,
+                if controller.translation { ", translated" } else { "" }
+            );
+            interrupts::without_interrupts(|| {
+                *CONTROLLER.lock() = Some(controller);
+            });
+        }
+        Err(error) => serial::println!("[FAILED] {:?}", error)
+    }
+}
+
+/// `None` if there is no controller or it failed to initialise.
+pub fn controller() -> Option<Controller> {
+    interrupts::without_interrupts(|| *CONTROLLER.lock())
+}
+
+fn initialise() -> Result<Controller, Ps2Error> {
+    let has_8042 = acpi::tables()
+        .and_then(|acpi| acpi.fadt.as_ref())
+        .map_or(true, |fadt| fadt.has_8042());
+    if !has_8042 {
+        return Err(Ps2Error::NoController);
+    }
+
+    write_command(DISABLE_FIRST)?;
+    write_command(DISABLE_SECOND)?;
+    flush();
+
+    let mut config = read_config()?;
+    config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATION);
+    write_config(config)?;
+    //The second port's clock can only be off if there is a second port.
+    let mut dual_channel = config & CONFIG_SECOND_CLOCK_OFF != 0;
+
+    write_command(SELF_TEST)?;
+    match read_data()? {
+        SELF_TEST_PASSED => {}
+        code => return Err(Ps2Error::SelfTestFailed(code))
+    }
+    //Some controllers come out of the self test reset.
+    write_config(config)?;
+
+    if dual_channel {
+        write_command(ENABLE_SECOND)?;
+        dual_channel = read_config()? & CONFIG_SECOND_CLOCK_OFF == 0;
+        write_command(DISABLE_SECOND)?;
+    }
+
+    let first_result = test_port(Ps2Port::First)?;
+    let first_works = first_result == PORT_TEST_PASSED;
+    let second_works = dual_channel && test_port(Ps2Port::Second)? == PORT_TEST_PASSED;
+    if !first_works && !second_works {
+        return Err(Ps2Error::PortTestFailed(Ps2Port::First, first_result));
+    }
+
+    if first_works {
+        write_command(ENABLE_FIRST)?;
+        config &= !CONFIG_FIRST_CLOCK_OFF;
+    }
+    if second_works {
+        write_command(ENABLE_SECOND)?;
+        config &= !CONFIG_SECOND_CLOCK_OFF;
+    }
+
+    let first = if first_works { detect(Ps2Port::First) } else { None };
+    let second = if second_works { detect(Ps2Port::Second) } else { None };
+
+    //`keyboard` only listens on the first port.
+    let mut translation = false;
+    if first.map_or(false, |device| device.is_keyboard()) {
+        translation = configure_keyboard()?;
+    }
+
+    if first_works { config |= CONFIG_FIRST_IRQ; }
+    if second_works { config |= CONFIG_SECOND_IRQ; }
+    if translation { config |= CONFIG_TRANSLATION; }
+    write_config(config)?;
+
+    Ok(Controller { dual_channel, first, second, translation })
+}
+
+/// Returns the test result, `PORT_TEST_PASSED` or a code for the fault.
+fn test_port(port : Ps2Port) -> Result<u8, Ps2Error> {
+    write_command(match port {
+        Ps2Port::First  => TEST_FIRST,
+        Ps2Port::Second => TEST_SECOND
+    })?;
+    read_data()
+}
+
+/// Resets the device on `port` and asks what it is, leaving it with scanning
+/// disabled. `None` if nothing answers.
+fn detect(port : Ps2Port) -> Option<DeviceType> {
+    send(port, DEVICE_RESET).ok()?;
+    if read_data_within(RESET_TIMEOUT).ok()? != DEVICE_SELF_TEST_PASSED {
+        return None;
+    }
+    //Mice follow the self test result with their ID, which we ask for again below.
+    let _ = read_data_within(RESET_ID_TIMEOUT);
+
+    send(port, DEVICE_DISABLE_SCANNING).ok()?;
+    send(port, DEVICE_IDENTIFY).ok()?;
+    let mut id = [0; 2];
+    let mut len = 0;
+    while len < id.len() {
+        match read_data() {
+            Ok(byte) => id[len] = byte,
+            Err(_) => break
+        }
+        len += 1;
+    }
+    Some(DeviceType::from_id(&id[..len]))
+}
+
+/// Picks the scancode set, sets the typematic rate and starts the keyboard
+/// scanning. Returns whether the controller has to translate.
+fn configure_keyboard() -> Result<bool, Ps2Error> {
+    let translation = match select_scancode_set() {
+        Some(set) => {
+            keyboard::set_scancode_set(set);
+            false
+        }
+        None => {
+            //Every keyboard speaks set 2, so let the controller turn it into set 1.
+            send(Ps2Port::First, DEVICE_SCANCODE_SET)?;
+            send(Ps2Port::First, 2)?;
+            keyboard::set_scancode_set(ScancodeSet::Set1);
+            true
+        }
+    };
+
+    if let Err(error) = keyboard::set_typematic(DEFAULT_TYPEMATIC_RATE, DEFAULT_TYPEMATIC_DELAY) {
+        serial::print!("(typematic rate not set: {:?})", error);
+    }
+    send(Ps2Port::First, DEVICE_ENABLE_SCANNING)?;
+    Ok(translation)
+}
+
+/// Asks for set 2, then set 1, and reads the set back each time since plenty
+/// of keyboards acknowledge sets they don't implement.
+fn select_scancode_set() -> Option<ScancodeSet> {
+    for &(set, id) in &[(ScancodeSet::Set2, 2), (ScancodeSet::Set1, 1)] {
+        if send(Ps2Port::First, DEVICE_SCANCODE_SET).is_err() || send(Ps2Port::First, id).is_err() {
+            continue;
+        }
+        if current_scancode_set() == Some(id) {
+            return Some(set);
+        }
+    }
+    None
+}
+
+fn current_scancode_set() -> Option<u8> {
+    send(Ps2Port::First, DEVICE_SCANCODE_SET).ok()?;
+    send(Ps2Port::First, 0).ok()?;
+    read_data().ok()
+}
+
+/// Sends a byte to the device on `port` and waits for the ACK, resending it
+/// if asked to. Polls for the response, so the port's interrupt has to be
+/// disabled or it will take the ACK first.
+pub fn send(port : Ps2Port, byte : u8) -> Result<(), Ps2Error> {
+    for _ in 0..RESEND_RETRIES {
+        if port == Ps2Port::Second {
+            write_command(WRITE_SECOND)?;
+        }
+        write_data(byte)?;
+        match read_data()? {
+            DEVICE_ACK => return Ok(()),
+            DEVICE_RESEND => continue,
+            other => return Err(Ps2Error::UnexpectedResponse(other))
+        }
+    }
+    Err(Ps2Error::Resend)
+}
+
+pub fn read_status() -> u8 {
+    unsafe {
+        Port::new(STATUS_PORT).read()
+    }
+}
+
+pub fn write_command(command : u8) -> Result<(), Ps2Error> {
+    wait_for_input_empty()?;
+    unsafe {
+        Port::new(COMMAND_PORT).write(command);
+    }
+    Ok(())
+}
+
+/// Writes a byte for the device on the first port.
+pub fn write_data(byte : u8) -> Result<(), Ps2Error> {
+    wait_for_input_empty()?;
+    unsafe {
+        Port::new(DATA_PORT).write(byte);
+    }
+    Ok(())
+}
+
+pub fn read_data() -> Result<u8, Ps2Error> {
+    read_data_within(RESPONSE_TIMEOUT)
+}
+
+fn read_data_within(timeout : Duration) -> Result<u8, Ps2Error> {
+    let deadline = tsc::Deadline::after(timeout);
+    while read_status() & STATUS_OUTPUT_FULL == 0 {
+        if deadline.has_passed() {
+            return Err(Ps2Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+    unsafe {
+        Ok(Port::new(DATA_PORT).read())
+    }
+}
+
+/// Throws away anything waiting in the output buffer.
+pub fn flush() {
+    for _ in 0..FLUSH_LIMIT {
+        if read_status() & STATUS_OUTPUT_FULL == 0 {
+            return;
+        }
+        unsafe {
+            let _ : u8 = Port::new(DATA_PORT).read();
+        }
+    }
+}
+
+fn wait_for_input_empty() -> Result<(), Ps2Error> {
+    let deadline = tsc::Deadline::after(RESPONSE_TIMEOUT);
+    while read_status() & STATUS_INPUT_FULL != 0 {
+        if deadline.has_passed() {
+            return Err(Ps2Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+fn read_config() -> Result<u8, Ps2Error> {
+    write_command(READ_CONFIG)?;
+    read_data()
+}
+
+fn write_config(config : u8) -> Result<(), Ps2Error> {
+    write_command(WRITE_CONFIG)?;
+    write_data(config)
+}
+
+#[test_case]
+fn test_device_type_from_id() {
+    assert_eq!(DeviceType::from_id(&[]), DeviceType::AtKeyboard);
+    assert_eq!(DeviceType::from_id(&[0x03]), DeviceType::WheelMouse);
+    assert_eq!(DeviceType::from_id(&[0xAB, 0x83]), DeviceType::Mf2Keyboard);
+    assert_eq!(DeviceType::from_id(&[0xAB, 0xC1]), DeviceType::Mf2KeyboardTranslated);
+    assert_eq!(DeviceType::from_id(&[0x42, 0x24]), DeviceType::Unknown(0x42, 0x24));
+    assert!(DeviceType::from_id(&[0x04]).is_mouse());
+    assert!(!DeviceType::from_id(&[0x04]).is_keyboard());
+}
@@ -0,0 +1,388 @@
+//rtc.rs
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::irq;
+use crate::serial;
+use crate::time;
+
+pub static CMOS_ADDRESS : u16 = 0x70;
+pub static CMOS_DATA    : u16 = 0x71;
+
+pub static REG_SECONDS        : u8 = 0x00;
+pub static REG_ALARM_SECONDS  : u8 = 0x01;
+pub static REG_MINUTES        : u8 = 0x02;
+pub static REG_ALARM_MINUTES  : u8 = 0x03;
+pub static REG_HOURS          : u8 = 0x04;
+pub static REG_ALARM_HOURS    : u8 = 0x05;
+pub static REG_DAY            : u8 = 0x07;
+pub static REG_MONTH          : u8 = 0x08;
+pub static REG_YEAR           : u8 = 0x09;
+pub static REG_STATUS_A       : u8 = 0x0A;
+pub static REG_STATUS_B       : u8 = 0x0B;
+pub static REG_STATUS_C       : u8 = 0x0C;
+pub static REG_CENTURY        : u8 = 0x32;
+
+pub static STATUS_A_UPDATE_IN_PROGRESS : u8 = 0b1000_0000;
+pub static STATUS_A_RATE_MASK          : u8 = 0b0000_1111;
+
+pub static STATUS_B_SET             : u8 = 0b1000_0000;
+pub static STATUS_B_PERIODIC        : u8 = 0b0100_0000;
+pub static STATUS_B_ALARM           : u8 = 0b0010_0000;
+pub static STATUS_B_UPDATE_ENDED    : u8 = 0b0001_0000;
+pub static STATUS_B_BINARY          : u8 = 0b0000_0100;
+pub static STATUS_B_24_HOUR         : u8 = 0b0000_0010;
+
+pub static STATUS_C_PERIODIC        : u8 = 0b0100_0000;
+pub static STATUS_C_ALARM           : u8 = 0b0010_0000;
+
+//12 hour mode flags afternoon hours with the top bit.
+static HOUR_PM : u8 = 0b1000_0000;
+
+//Line on the secondary PIC, vector `pics::InterruptIndex::Rtc`.
+pub static RTC_IRQ : u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year   : u16,
+    pub month  : u8,
+    pub day    : u8,
+    pub hour   : u8,
+    pub minute : u8,
+    pub second : u8
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00, the RTC is assumed to keep UTC.
+    pub fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        days as u64 * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    pub fn from_unix(seconds : u64) -> DateTime {
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let time = seconds % 86400;
+        DateTime {
+            year   : year as u16,
+            month  : month as u8,
+            day    : day as u8,
+            hour   : (time / 3600) as u8,
+            minute : (time / 60 % 60) as u8,
+            second : (time % 60) as u8
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+//Days since the unix epoch of a proleptic Gregorian date, and back.
+fn days_from_civil(year : i64, month : i64, day : i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days : i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn bcd_to_binary(value : u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+fn binary_to_bcd(value : u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Register values as the RTC stores them, before mode conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second  : u8,
+    minute  : u8,
+    hour    : u8,
+    day     : u8,
+    month   : u8,
+    year    : u8,
+    century : u8
+}
+
+/// How the RTC encodes values, from status register B.
+#[derive(Debug, Clone, Copy)]
+struct Format {
+    binary      : bool,
+    twenty_four : bool
+}
+
+impl Format {
+    fn current() -> Format {
+        let status_b = read_register(REG_STATUS_B);
+        Format {
+            binary      : status_b & STATUS_B_BINARY != 0,
+            twenty_four : status_b & STATUS_B_24_HOUR != 0
+        }
+    }
+
+    fn decode(&self, value : u8) -> u8 {
+        if self.binary { value } else { bcd_to_binary(value) }
+    }
+
+    fn encode(&self, value : u8) -> u8 {
+        if self.binary { value } else { binary_to_bcd(value) }
+    }
+
+    fn decode_hour(&self, value : u8) -> u8 {
+        if self.twenty_four {
+            return self.decode(value);
+        }
+        let pm = value & HOUR_PM != 0;
+        let hour = self.decode(value & !HOUR_PM) % 12;
+        if pm { hour + 12 } else { hour }
+    }
+
+    fn encode_hour(&self, hour : u8) -> u8 {
+        if self.twenty_four {
+            return self.encode(hour);
+        }
+        let twelve = if hour % 12 == 0 { 12 } else { hour % 12 };
+        self.encode(twelve) | if hour >= 12 { HOUR_PM } else { 0 }
+    }
+
+    fn decode_time(&self, raw : RawTime) -> DateTime {
+        let year = self.decode(raw.year) as u16;
+        let century = self.decode(raw.century) as u16;
+        //Not every machine has a century register, fall back to a window.
+        let year = if (19..=21).contains(&century) {
+            century * 100 + year
+        } else if year < 70 {
+            2000 + year
+        } else {
+            1900 + year
+        };
+
+        DateTime {
+            year   : year,
+            month  : self.decode(raw.month),
+            day    : self.decode(raw.day),
+            hour   : self.decode_hour(raw.hour),
+            minute : self.decode(raw.minute),
+            second : self.decode(raw.second)
+        }
+    }
+}
+
+fn read_register(register : u8) -> u8 {
+    without_interrupts(|| unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(register);
+        Port::<u8>::new(CMOS_DATA).read()
+    })
+}
+
+fn write_register(register : u8, value : u8) {
+    without_interrupts(|| unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(register);
+        Port::<u8>::new(CMOS_DATA).write(value);
+    })
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+}
+
+fn read_raw() -> RawTime {
+    RawTime {
+        second  : read_register(REG_SECONDS),
+        minute  : read_register(REG_MINUTES),
+        hour    : read_register(REG_HOURS),
+        day     : read_register(REG_DAY),
+        month   : read_register(REG_MONTH),
+        year    : read_register(REG_YEAR),
+        century : read_register(REG_CENTURY)
+    }
+}
+
+/// Reads the date and time straight from the RTC. Waits out any update in
+/// progress and re-reads until two reads agree, so a rollover between
+/// registers can't produce a torn value.
+pub fn read_time() -> DateTime {
+    let mut last;
+    loop {
+        while update_in_progress() {}
+        last = read_raw();
+        while update_in_progress() {}
+        if read_raw() == last {
+            break;
+        }
+    }
+    Format::current().decode_time(last)
+}
+
+/// Nanoseconds since the unix epoch at `time::uptime() == 0`.
+static BOOT_EPOCH : AtomicU64 = AtomicU64::new(0);
+
+fn sync_epoch(time : DateTime) {
+    let nanos = time.to_unix() as u128 * 1_000_000_000;
+    let uptime = time::uptime().as_nanos();
+    BOOT_EPOCH.store(nanos.saturating_sub(uptime) as u64, Ordering::Relaxed);
+}
+
+/// Reads the RTC once to anchor wall clock time to the monotonic clock.
+pub fn init() {
+    serial::print!("Reading RTC...");
+    let time = read_time();
+    sync_epoch(time);
+    //The RTC only raises IRQ8 once periodic or alarm interrupts are on.
+    irq::register(RTC_IRQ, handle_interrupt).expect("RTC IRQ already taken");
+    serial::println!("[OK] {}", time);
+}
+
+/// Time since the unix epoch, advanced by the timer rather than by reading
+/// the RTC on every call.
+pub fn unix_time() -> Duration {
+    let nanos = BOOT_EPOCH.load(Ordering::Relaxed) as u128 + time::uptime().as_nanos();
+    Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32)
+}
+
+pub fn now() -> DateTime {
+    DateTime::from_unix(unix_time().as_secs())
+}
+
+/// Sets the RTC, keeping whatever BCD and 12/24 hour format it is in, and
+/// moves `now()` to match.
+pub fn set_time(time : DateTime) {
+    let format = Format::current();
+    without_interrupts(|| {
+        //Stop the clock updating while the registers are inconsistent.
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_SET);
+
+        write_register(REG_SECONDS, format.encode(time.second));
+        write_register(REG_MINUTES, format.encode(time.minute));
+        write_register(REG_HOURS, format.encode_hour(time.hour));
+        write_register(REG_DAY, format.encode(time.day));
+        write_register(REG_MONTH, format.encode(time.month));
+        write_register(REG_YEAR, format.encode((time.year % 100) as u8));
+        write_register(REG_CENTURY, format.encode((time.year / 100) as u8));
+
+        write_register(REG_STATUS_B, status_b & !STATUS_B_SET);
+    });
+    sync_epoch(time);
+}
+
+static PERIODIC_TICKS : AtomicU64 = AtomicU64::new(0);
+static ALARM_HANDLER : Mutex<Option<fn()>> = Mutex::new(None);
+
+/// Enables the periodic interrupt at `32768 >> (rate - 1)` Hz. `rate` is
+/// clamped to 3 (8192 Hz) to 15 (2 Hz).
+pub fn enable_periodic(rate : u8) {
+    let rate = rate.max(3).min(15);
+    without_interrupts(|| {
+        let status_a = read_register(REG_STATUS_A);
+        write_register(REG_STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_PERIODIC);
+    });
+    enable_irq();
+}
+
+pub fn disable_periodic() {
+    without_interrupts(|| {
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b & !STATUS_B_PERIODIC);
+    });
+}
+
+/// Periodic interrupts seen since they were first enabled.
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+/// Calls `handler` from the RTC interrupt each day at `hour:minute:second`.
+/// The handler runs in interrupt context, so it must not block or allocate.
+pub fn set_alarm(hour : u8, minute : u8, second : u8, handler : fn()) {
+    let format = Format::current();
+    without_interrupts(|| {
+        *ALARM_HANDLER.lock() = Some(handler);
+        write_register(REG_ALARM_HOURS, format.encode_hour(hour));
+        write_register(REG_ALARM_MINUTES, format.encode(minute));
+        write_register(REG_ALARM_SECONDS, format.encode(second));
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_ALARM);
+    });
+    enable_irq();
+}
+
+pub fn clear_alarm() {
+    without_interrupts(|| {
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b & !STATUS_B_ALARM);
+        *ALARM_HANDLER.lock() = None;
+    });
+}
+
+fn enable_irq() {
+    //Throw away any interrupt that was already pending, it won't be raised
+    //again until register C has been read.
+    read_register(REG_STATUS_C);
+}
+
+fn handle_interrupt() {
+    //Reading register C acknowledges the interrupt.
+    let status_c = read_register(REG_STATUS_C);
+    if status_c & STATUS_C_PERIODIC != 0 {
+        PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+    if status_c & STATUS_C_ALARM != 0 {
+        if let Some(handler) = *ALARM_HANDLER.lock() {
+            handler();
+        }
+    }
+}
+
+#[test_case]
+fn test_bcd() {
+    assert_eq!(bcd_to_binary(0x59), 59);
+    assert_eq!(binary_to_bcd(59), 0x59);
+    assert_eq!(bcd_to_binary(binary_to_bcd(7)), 7);
+}
+
+#[test_case]
+fn test_twelve_hour_mode() {
+    let format = Format { binary : false, twenty_four : false };
+    assert_eq!(format.decode_hour(0x12), 0);
+    assert_eq!(format.decode_hour(0x12 | HOUR_PM), 12);
+    assert_eq!(format.decode_hour(0x11 | HOUR_PM), 23);
+    assert_eq!(format.encode_hour(0), 0x12);
+    assert_eq!(format.encode_hour(13), 0x01 | HOUR_PM);
+}
+
+#[test_case]
+fn test_unix_time() {
+    let epoch = DateTime { year : 1970, month : 1, day : 1, hour : 0, minute : 0, second : 0 };
+    assert_eq!(epoch.to_unix(), 0);
+
+    let leap_day = DateTime { year : 2024, month : 2, day : 29, hour : 13, minute : 37, second : 5 };
+    assert_eq!(leap_day.to_unix(), 1_709_213_825);
+    assert_eq!(DateTime::from_unix(1_709_213_825), leap_day);
+}
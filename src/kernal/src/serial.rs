@@ -1,8 +1,16 @@
 use core::fmt;
 use core::fmt::Write;
+use x86_64::instructions::interrupts;
 
 pub fn _print(args : fmt::Arguments) {
-    SERIAL1.lock().write_fmt(args).expect("Unable To Write To the Serial Port");
+    //Interrupt handlers print too, so hold the lock with interrupts off.
+    interrupts::without_interrupts(|| {
+        SERIAL1.lock().write_fmt(args).expect("Unable To Write To the Serial Port");
+    });
+}
+
+pub fn is_locked() -> bool {
+    SERIAL1.try_lock().is_none()
 }
 
 pub macro print($($arg:tt)*) {
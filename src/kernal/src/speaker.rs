@@ -0,0 +1,144 @@
+//speaker.rs
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::pit;
+use crate::time;
+use crate::timer;
+
+pub static BELL_FREQUENCY : u32 = 880;
+pub static BELL_DURATION  : u64 = 100;
+
+/// Longest melody `play` will queue, anything past it is dropped.
+pub const QUEUE_LIMIT : usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    /// Tone in Hz, 0 for a rest.
+    pub frequency : u32,
+    pub duration  : u64
+}
+
+impl Note {
+    pub const fn new(frequency : u32, duration : u64) -> Note {
+        Note { frequency : frequency, duration : duration }
+    }
+
+    pub const fn rest(duration : u64) -> Note {
+        Note { frequency : 0, duration : duration }
+    }
+}
+
+struct Melody {
+    notes   : VecDeque<Note>,
+    playing : bool
+}
+
+lazy_static! {
+    static ref MELODY : Mutex<Melody> = Mutex::new(Melody {
+        notes   : VecDeque::new(),
+        playing : false
+    });
+}
+
+/// Starts a square wave at `frequency` Hz until `stop` is called.
+pub fn start_tone(frequency : u32) {
+    unsafe {
+        pit::program(pit::CHANNEL_2, pit::DATA_PORT_2, pit::MODE_3, pit::reload_for(frequency));
+    }
+    without_interrupts(|| unsafe {
+        let mut port : Port<u8> = Port::new(pit::CHANNEL_2_CONTROL_PORT);
+        let value = port.read();
+        port.write(value | pit::CHANNEL_2_GATE | pit::CHANNEL_2_SPEAKER);
+    });
+}
+
+pub fn stop() {
+    without_interrupts(|| unsafe {
+        let mut port : Port<u8> = Port::new(pit::CHANNEL_2_CONTROL_PORT);
+        let value = port.read();
+        port.write(value & !(pit::CHANNEL_2_GATE | pit::CHANNEL_2_SPEAKER));
+    });
+}
+
+/// Plays a tone, blocking the calling thread for `duration` ms.
+pub fn beep(frequency : u32, duration : u64) {
+    start_tone(frequency);
+    time::sleep(duration);
+    stop();
+}
+
+/// Queues `notes` behind whatever is already playing and returns straight
+/// away; the timer thread steps through the queue.
+pub fn play(notes : &[Note]) {
+    let start = without_interrupts(|| {
+        let mut melody = MELODY.lock();
+        let room = QUEUE_LIMIT.saturating_sub(melody.notes.len());
+        melody.notes.extend(notes.iter().take(room));
+
+        let start = !melody.playing && !melody.notes.is_empty();
+        if start {
+            melody.playing = true;
+        }
+        start
+    });
+    if start {
+        next_note();
+    }
+}
+
+pub fn is_playing() -> bool {
+    without_interrupts(|| MELODY.lock().playing)
+}
+
+/// Drops the queued notes and silences the speaker once the current note
+/// ends.
+pub fn clear_queue() {
+    without_interrupts(|| MELODY.lock().notes.clear());
+}
+
+static BELL_PENDING : AtomicBool = AtomicBool::new(false);
+
+/// Short beep for `\x07`. The terminal calls this with its lock held and
+/// interrupts off, so it only flags the bell and leaves the timer thread to
+/// play it. Does nothing before the timers are up.
+pub fn bell() {
+    BELL_PENDING.store(true, Ordering::Release);
+    if !timer::nudge() {
+        BELL_PENDING.store(false, Ordering::Release);
+    }
+}
+
+/// Called by the timer thread. Bells that arrive while a melody is playing
+/// are dropped.
+pub(crate) fn play_pending_bell() {
+    if BELL_PENDING.swap(false, Ordering::AcqRel) && !is_playing() {
+        play(&[Note::new(BELL_FREQUENCY, BELL_DURATION)]);
+    }
+}
+
+fn next_note() {
+    let note = without_interrupts(|| {
+        let mut melody = MELODY.lock();
+        let note = melody.notes.pop_front();
+        melody.playing = note.is_some();
+        note
+    });
+
+    match note {
+        Some(note) => {
+            if note.frequency == 0 {
+                stop();
+            } else {
+                start_tone(note.frequency);
+            }
+            timer::after(note.duration, next_note);
+        }
+        None => stop()
+    }
+}
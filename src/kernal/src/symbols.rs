@@ -0,0 +1,116 @@
+//symbols.rs
+
+use core::{fmt, slice, str};
+
+/// Space reserved in the kernel image for the symbol table. `tools/ksyms.rs`
+/// fills it in as part of linking, from `tools/link.sh`, and fails the build
+/// if the table doesn't fit.
+pub const KSYMS_SIZE : usize = 512 * 1024;
+
+const KSYMS_MAGIC : [u8; 4] = *b"KSYM";
+const HEADER_SIZE : usize = 16;
+
+/// Layout shared with `tools/ksyms.rs`: a header, `count` entries sorted by
+/// address, then a table of NUL terminated names.
+#[repr(C, align(16))]
+struct SymbolTable {
+    magic          : [u8; 4],
+    count          : u32,
+    strings_offset : u32,
+    reserved       : u32,
+    data           : [u8; KSYMS_SIZE - HEADER_SIZE]
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SymbolEntry {
+    address     : u64,
+    size        : u32,
+    name_offset : u32
+}
+
+//`static mut` so the compiler can't assume the table is still empty, the
+//real contents are patched into the ELF after the kernel is linked.
+#[used]
+#[link_section = ".ksyms"]
+static mut KSYMS : SymbolTable = SymbolTable {
+    magic          : KSYMS_MAGIC,
+    count          : 0,
+    strings_offset : 0,
+    reserved       : 0,
+    data           : [0; KSYMS_SIZE - HEADER_SIZE]
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name    : &'static str,
+    pub address : u64,
+    pub offset  : u64
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+0x{:x}", self.name, self.offset)
+    }
+}
+
+/// Finds the function containing `address`.
+pub fn lookup(address : u64) -> Option<Symbol> {
+    let entries = entries()?;
+
+    let index = match entries.binary_search_by_key(&address, |e| e.address) {
+        Ok(index) => index,
+        Err(0) => return None,
+        Err(index) => index - 1
+    };
+    let entry = entries[index];
+
+    if entry.size != 0 && address >= entry.address + entry.size as u64 {
+        return None;
+    }
+
+    Some(Symbol {
+        name    : name(entry.name_offset)?,
+        address : entry.address,
+        offset  : address - entry.address
+    })
+}
+
+/// Symbolises a return address, looking up the call instruction before it so
+/// calls at the very end of a function resolve to the right symbol.
+pub fn lookup_return_address(address : u64) -> Option<Symbol> {
+    let symbol = lookup(address.checked_sub(1)?)?;
+    Some(Symbol { offset : address - symbol.address, ..symbol })
+}
+
+pub fn is_loaded() -> bool {
+    entries().is_some()
+}
+
+fn table() -> &'static SymbolTable {
+    unsafe { &KSYMS }
+}
+
+fn entries() -> Option<&'static [SymbolEntry]> {
+    let table = table();
+    let count = table.count as usize;
+    if table.magic != KSYMS_MAGIC || count == 0 {
+        return None;
+    }
+    if count * core::mem::size_of::<SymbolEntry>() > table.data.len() {
+        return None;
+    }
+
+    let start = table.data.as_ptr() as *const SymbolEntry;
+    Some(unsafe { slice::from_raw_parts(start, count) })
+}
+
+fn name(name_offset : u32) -> Option<&'static str> {
+    let table = table();
+    let start = (table.strings_offset as usize)
+        .checked_add(name_offset as usize)?
+        .checked_sub(HEADER_SIZE)?;
+    let bytes = table.data.get(start..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    str::from_utf8(&bytes[..len]).ok()
+}
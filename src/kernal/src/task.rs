@@ -0,0 +1,45 @@
+//task.rs
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+pub mod executor;
+pub mod keyboard;
+
+pub use executor::Executor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> TaskId {
+        static NEXT_ID : AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A pinned, heap allocated future the executor drives to completion.
+pub struct Task {
+    id     : TaskId,
+    future : Pin<Box<dyn Future<Output = ()>>>
+}
+
+impl Task {
+    pub fn new(future : impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id     : TaskId::new(),
+            future : Box::pin(future)
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn poll(&mut self, context : &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
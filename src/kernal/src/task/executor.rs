@@ -0,0 +1,139 @@
+//task/executor.rs
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+use super::{Task, TaskId};
+
+const TASK_QUEUE_SIZE : usize = 100;
+
+/// Runs tasks whenever they are woken and halts the CPU until the next
+/// interrupt when nothing is ready.
+pub struct Executor {
+    tasks       : BTreeMap<TaskId, Task>,
+    task_queue  : Arc<ArrayQueue<TaskId>>,
+    //Set when a wakeup didn't fit in the queue.
+    overflowed  : Arc<AtomicBool>,
+    waker_cache : BTreeMap<TaskId, Waker>
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        Executor {
+            tasks       : BTreeMap::new(),
+            task_queue  : Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            overflowed  : Arc::new(AtomicBool::new(false)),
+            waker_cache : BTreeMap::new()
+        }
+    }
+
+    pub fn spawn(&mut self, task : Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("Task with ID {:?} already exists", task_id);
+        }
+        queue(&self.task_queue, &self.overflowed, task_id);
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Runs until every spawned task has finished.
+    pub fn run_until_complete(&mut self) {
+        while !self.tasks.is_empty() {
+            self.run_ready_tasks();
+            if !self.tasks.is_empty() {
+                self.sleep_if_idle();
+            }
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        while let Ok(task_id) = self.task_queue.pop() {
+            self.poll_task(task_id);
+        }
+
+        //We don't know whose wakeups were lost, so give every task a turn.
+        if self.overflowed.swap(false, Ordering::AcqRel) {
+            let task_ids : Vec<TaskId> = self.tasks.keys().copied().collect();
+            for task_id in task_ids {
+                self.poll_task(task_id);
+            }
+        }
+    }
+
+    fn poll_task(&mut self, task_id : TaskId) {
+        let Self { tasks, task_queue, overflowed, waker_cache } = self;
+
+        let task = match tasks.get_mut(&task_id) {
+            Some(task) => task,
+            //The task already finished, this was a stale wakeup.
+            None => return
+        };
+
+        let waker = waker_cache
+            .entry(task_id)
+            .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone(), overflowed.clone()));
+        let mut context = Context::from_waker(waker);
+
+        if let Poll::Ready(()) = task.poll(&mut context) {
+            tasks.remove(&task_id);
+            waker_cache.remove(&task_id);
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        //Interrupts are disabled for the check so a wakeup can't slip in
+        //between seeing an empty queue and halting.
+        interrupts::disable();
+        if self.task_queue.is_empty() && !self.overflowed.load(Ordering::Acquire) {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Wakers can fire from interrupt handlers, so a full queue is flagged for
+/// the executor instead of panicking.
+fn queue(task_queue : &ArrayQueue<TaskId>, overflowed : &AtomicBool, task_id : TaskId) {
+    if task_queue.push(task_id).is_err() {
+        overflowed.store(true, Ordering::Release);
+    }
+}
+
+struct TaskWaker {
+    task_id    : TaskId,
+    task_queue : Arc<ArrayQueue<TaskId>>,
+    overflowed : Arc<AtomicBool>
+}
+
+impl TaskWaker {
+    fn new(task_id : TaskId, task_queue : Arc<ArrayQueue<TaskId>>, overflowed : Arc<AtomicBool>) -> Waker {
+        Waker::from(Arc::new(TaskWaker { task_id, task_queue, overflowed }))
+    }
+
+    fn wake_task(&self) {
+        queue(&self.task_queue, &self.overflowed, self.task_id);
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self : Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self : &Arc<Self>) {
+        self.wake_task();
+    }
+}
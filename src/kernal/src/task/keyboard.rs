@@ -0,0 +1,32 @@
+//task/keyboard.rs
+
+use futures_util::stream::StreamExt;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::input;
+use crate::terminal;
+
+/// Echoes keyboard input to the terminal.
+pub async fn print_keypresses() {
+    let mut keys = input::keys();
+
+    while let Some(key) = keys.next().await {
+        match key {
+            DecodedKey::Unicode(key) => {
+                if key == '\u{8}' {
+                    terminal::backspace()
+                } else if terminal::get_column() < 79 {
+                    terminal::print!("{:}", key);
+                } else {
+                    terminal::newline();
+                }
+            }
+            DecodedKey::RawKey(KeyCode::F1) => {
+                terminal::clear!();
+                terminal::set_position!(0,0);
+                terminal::update_cursor();
+            }
+            _ => {}
+        }
+    }
+}
@@ -35,6 +35,7 @@ impl Terminal {
         for b in s.as_bytes() {
             match b {
             	0x20..=0x7e | b'\n' | b'\t' => { self._print_byte(*b) }
+                0x07 =>			{ crate::speaker::bell() }
                 _ =>			{ self._print_byte(0xFE) }
             }
         }
@@ -198,6 +199,16 @@ pub macro set_background($color:expr) {
     crate::terminal::_set_bg_color($color);
 }
 
+/// Releases the terminal lock regardless of who holds it. Only for the panic
+/// path, where the holder is never going to run again.
+pub(crate) unsafe fn force_unlock() {
+    TERMINAL.force_unlock();
+}
+
+pub(crate) fn is_locked() -> bool {
+    TERMINAL.try_lock().is_none()
+}
+
 #[doc(hidden)]
 pub fn _print(args : fmt::Arguments) {
     use core::fmt::Write;
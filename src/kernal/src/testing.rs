@@ -0,0 +1,67 @@
+//testing.rs
+
+use core::panic::PanicInfo;
+use x86_64::instructions::port::Port;
+
+use crate::serial;
+
+//Matches the `-device isa-debug-exit,iobase=0xf4,iosize=0x04` bootimage test-arg.
+pub static ISA_DEBUG_EXIT_PORT : u16 = 0xf4;
+
+/// QEMU exits with `(code << 1) | 1`, so `Success` shows up as 33, which is
+/// what `test-success-exit-code` is set to in Cargo.toml.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed  = 0x11
+}
+
+pub fn exit_qemu(exit_code : QemuExitCode) -> ! {
+    unsafe {
+        let mut port = Port::new(ISA_DEBUG_EXIT_PORT);
+        port.write(exit_code as u32);
+    }
+    crate::spin!();
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T> Testable for T where T : Fn() {
+    fn run(&self) {
+        serial::print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial::println!("[ok]");
+    }
+}
+
+pub fn test_runner(tests : &[&dyn Testable]) {
+    serial::println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+pub fn test_panic_handler(info : &PanicInfo) -> ! {
+    serial::println!("[failed]\n");
+    serial::println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+}
+
+/// Runs a test that is expected to panic, for test kernels built with
+/// `harness = false`. Returning from `test` fails the run, the panic itself
+/// has to be caught by a panic handler calling `should_panic_handler`.
+pub fn should_panic(name : &str, test : fn()) -> ! {
+    serial::print!("{}...\t", name);
+    test();
+    serial::println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+}
+
+pub fn should_panic_handler(_info : &PanicInfo) -> ! {
+    serial::println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+}
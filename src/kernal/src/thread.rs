@@ -0,0 +1,220 @@
+//thread.rs
+
+use alloc::boxed::Box;
+use alloc::vec;
+use core::sync::atomic::Ordering;
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub mod context;
+pub mod scheduler;
+
+use scheduler::{Scheduler, ThreadState, SCHEDULER};
+use crate::serial;
+
+/// Software interrupt `yield_now` raises to get the scheduler to switch.
+pub const YIELD_VECTOR : u8 = 0x81;
+
+pub const STACK_SIZE : usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    pub(crate) fn from_u64(id : u64) -> ThreadId {
+        ThreadId(id)
+    }
+}
+
+/// Higher priority threads always run before lower ones, threads of the
+/// same priority take turns each time slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High
+}
+
+/// Owned permission to join a thread. Dropping it detaches the thread, which
+/// is then cleaned up by itself once it finishes.
+#[derive(Debug)]
+pub struct JoinHandle {
+    id : ThreadId
+}
+
+impl JoinHandle {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Blocks the calling thread until this one has finished.
+    pub fn join(self) {
+        let id = self.id;
+        core::mem::forget(self);
+
+        loop {
+            let finished = without_interrupts(|| {
+                let mut guard = SCHEDULER.lock();
+                let scheduler = guard.as_mut().expect("Threads not initialised");
+                match scheduler.find(id).map(|t| t.state) {
+                    Some(ThreadState::Finished) => scheduler.take_finished(id),
+                    Some(_) => {
+                        scheduler.current().state = ThreadState::Joining(id);
+                        return None;
+                    }
+                    None => panic!("Joined unknown thread {:?}", id)
+                }
+            });
+
+            if let Some(thread) = finished {
+                drop(thread);
+                return;
+            }
+            yield_now();
+        }
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        let id = self.id;
+        without_interrupts(|| {
+            let mut guard = SCHEDULER.lock();
+            if let Some(thread) = guard.as_mut().and_then(|s| s.find(id)) {
+                thread.detached = true;
+            }
+        });
+        reap();
+    }
+}
+
+/// Sets up the scheduler, turning the code that is running into the first
+/// thread. Must run after `allocator::init_heap`; nothing is switched until
+/// interrupts are enabled.
+pub fn init() {
+    serial::print!("Initialising Scheduler...");
+    let scheduler = Scheduler::new(new_stack());
+    without_interrupts(|| {
+        *SCHEDULER.lock() = Some(scheduler);
+    });
+    serial::println!("[OK]");
+}
+
+pub fn spawn<F>(f : F) -> JoinHandle where F : FnOnce() + Send + 'static {
+    spawn_with_priority(Priority::Normal, f)
+}
+
+pub fn spawn_with_priority<F>(priority : Priority, f : F) -> JoinHandle
+    where F : FnOnce() + Send + 'static
+{
+    reap();
+
+    let entry = Box::new(f);
+    let stack = new_stack();
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().expect("Threads not initialised");
+        let (_, id) = scheduler.add_thread(priority, entry, stack)
+            .expect("Too many threads");
+        JoinHandle { id : id }
+    })
+}
+
+/// Gives up the rest of the current time slice.
+pub fn yield_now() {
+    unsafe {
+        //Must match YIELD_VECTOR
+        asm!("int 0x81");
+    }
+}
+
+/// Blocks the current thread until the timer has ticked `tick` times, see
+/// `time::sleep` for the public interface. Returns false without blocking if
+/// the scheduler isn't running yet.
+pub(crate) fn sleep_until_tick(tick : u64) -> bool {
+    let blocked = block_until_tick(tick);
+    if blocked {
+        yield_now();
+    }
+    blocked
+}
+
+/// Marks the current thread as sleeping without switching away yet, so the
+/// caller can release its own locks first and then `yield_now`. A `wake` in
+/// between is not lost, the thread just doesn't block.
+pub(crate) fn block_until_tick(tick : u64) -> bool {
+    without_interrupts(|| {
+        match SCHEDULER.lock().as_mut() {
+            Some(scheduler) => {
+                scheduler.current().state = ThreadState::Sleeping(tick);
+                true
+            }
+            None => false
+        }
+    })
+}
+
+/// Makes a sleeping thread ready again before its wake up tick.
+pub(crate) fn wake(id : ThreadId) {
+    without_interrupts(|| {
+        if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+            scheduler.wake(id);
+        }
+    });
+}
+
+/// Like `wake`, but only leaves the request for the next timer tick. It
+/// takes no locks, so it is safe with interrupts off and other locks held.
+/// There is a single slot, a second request before the tick replaces the
+/// first, and a thread that isn't asleep by then ignores it. Only
+/// `timer::nudge` uses it, which covers the second case with its own flag,
+/// anything else should go through `nudge` too.
+pub(crate) fn wake_on_next_tick(id : ThreadId) {
+    scheduler::DEFERRED_WAKE.store(id.0, Ordering::Release);
+}
+
+pub fn current_id() -> ThreadId {
+    without_interrupts(|| {
+        SCHEDULER.lock().as_ref().expect("Threads not initialised").current_id()
+    })
+}
+
+fn new_stack() -> Box<[u8]> {
+    vec![0u8; STACK_SIZE].into_boxed_slice()
+}
+
+/// Frees the stacks of detached threads that have finished. Only ever called
+/// from thread context, so the timer interrupt never has to free memory.
+fn reap() {
+    loop {
+        let thread = without_interrupts(|| {
+            SCHEDULER.lock().as_mut().and_then(|s| s.take_detached())
+        });
+        match thread {
+            Some(thread) => drop(thread),
+            None => return
+        }
+    }
+}
+
+/// Every spawned thread starts here, on its own stack, with interrupts enabled.
+extern "C" fn thread_entry() -> ! {
+    let entry = without_interrupts(|| {
+        SCHEDULER.lock().as_mut().and_then(|s| s.current().entry.take())
+    });
+    if let Some(entry) = entry {
+        entry();
+    }
+
+    without_interrupts(|| {
+        SCHEDULER.lock().as_mut().expect("Threads not initialised").finish_current();
+    });
+    //A finished thread is never picked again, this only returns if the
+    //yield raced with the lock being held elsewhere.
+    loop {
+        yield_now();
+    }
+}
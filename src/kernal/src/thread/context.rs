@@ -0,0 +1,86 @@
+//thread/context.rs
+
+use x86_64::structures::idt::HandlerFunc;
+
+use super::scheduler;
+
+/// Register state of a thread that isn't running, as laid out on its stack
+/// by the entry points below: the general purpose registers we push,
+/// followed by the frame the CPU pushed when the interrupt was taken.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SavedContext {
+    pub r15 : u64, pub r14 : u64, pub r13 : u64, pub r12 : u64,
+    pub r11 : u64, pub r10 : u64, pub r9  : u64, pub r8  : u64,
+    pub rbp : u64, pub rdi : u64, pub rsi : u64, pub rdx : u64,
+    pub rcx : u64, pub rbx : u64, pub rax : u64,
+
+    pub rip : u64, pub cs : u64, pub rflags : u64, pub rsp : u64, pub ss : u64
+}
+
+//Interrupts enabled, plus the always-set reserved bit 1.
+const INITIAL_RFLAGS : u64 = 0x202;
+
+/// Lays out a context on a fresh stack that "returns" into `entry` with
+/// interrupts enabled. Returns the stack pointer to switch to.
+pub fn initial_stack(stack : &mut [u8], entry : extern "C" fn() -> !) -> u64 {
+    let stack_top = (stack.as_mut_ptr() as u64 + stack.len() as u64) & !0xF;
+
+    //`entry` starts as if it had been called, with a null return address
+    //at the top of the stack and rsp 8 bytes off 16 byte alignment.
+    let entry_rsp = stack_top - 8;
+    unsafe { *(entry_rsp as *mut u64) = 0; }
+
+    let context_addr = entry_rsp - core::mem::size_of::<SavedContext>() as u64;
+    let context = SavedContext {
+        rip    : entry as u64,
+        cs     : x86_64::instructions::segmentation::cs().0 as u64,
+        rflags : INITIAL_RFLAGS,
+        rsp    : entry_rsp,
+        ss     : 0,
+        ..SavedContext::default()
+    };
+    unsafe { (context_addr as *mut SavedContext).write(context); }
+
+    context_addr
+}
+
+/// Generates an interrupt entry point that saves the interrupted thread's
+/// registers on its own stack, passes that stack pointer to `$handler`, and
+/// resumes whichever thread's stack pointer `$handler` returns.
+macro context_switch_entry($name:ident, $handler:path) {
+    #[naked]
+    extern "C" fn $name() {
+        unsafe {
+            asm!(
+                "push rax", "push rbx", "push rcx", "push rdx",
+                "push rsi", "push rdi", "push rbp",
+                "push r8", "push r9", "push r10", "push r11",
+                "push r12", "push r13", "push r14", "push r15",
+                "mov rdi, rsp",
+                "call {handler}",
+                "mov rsp, rax",
+                "pop r15", "pop r14", "pop r13", "pop r12",
+                "pop r11", "pop r10", "pop r9", "pop r8",
+                "pop rbp", "pop rdi", "pop rsi",
+                "pop rdx", "pop rcx", "pop rbx", "pop rax",
+                "iretq",
+                handler = sym $handler,
+                options(noreturn)
+            );
+        }
+    }
+}
+
+context_switch_entry!(timer_entry, scheduler::timer_switch);
+context_switch_entry!(yield_entry, scheduler::yield_switch);
+
+//The IDT only takes `x86-interrupt` handlers, but the naked entries do their
+//own saving and `iretq`, so all the gate needs is their address.
+pub fn timer_handler() -> HandlerFunc {
+    unsafe { core::mem::transmute(timer_entry as extern "C" fn()) }
+}
+
+pub fn yield_handler() -> HandlerFunc {
+    unsafe { core::mem::transmute(yield_entry as extern "C" fn()) }
+}
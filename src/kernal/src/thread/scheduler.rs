@@ -0,0 +1,268 @@
+//thread/scheduler.rs
+
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::context;
+use super::{Priority, ThreadId};
+use crate::irq;
+use crate::pics;
+use crate::time;
+
+pub const MAX_THREADS : usize = 64;
+
+//Timer ticks a thread may run before others of the same priority get a turn.
+const TIME_SLICE : u32 = 10;
+
+//No thread is ever given this id.
+const NO_THREAD : u64 = u64::MAX;
+
+/// Thread `wake_on_next_tick` asked for, picked up by the timer interrupt.
+pub(super) static DEFERRED_WAKE : AtomicU64 = AtomicU64::new(NO_THREAD);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    Sleeping(u64),
+    Joining(ThreadId),
+    Finished
+}
+
+pub(super) struct Thread {
+    pub(super) id       : ThreadId,
+    pub(super) priority : Priority,
+    pub(super) state    : ThreadState,
+    pub(super) detached : bool,
+    pub(super) entry    : Option<Box<dyn FnOnce() + Send + 'static>>,
+    rsp                 : u64,
+    //`None` for the boot thread, which runs on the bootloader's stack.
+    _stack              : Option<Box<[u8]>>
+}
+
+/// Round robin within the highest priority that has a ready thread.
+///
+/// Runs from the timer interrupt, so nothing in the switching path may
+/// allocate or free: threads are only created and reaped from thread context.
+pub(super) struct Scheduler {
+    threads         : [Option<Thread>; MAX_THREADS],
+    current         : usize,
+    idle            : usize,
+    slice_remaining : u32,
+    next_id         : u64
+}
+
+impl Scheduler {
+    pub(super) fn new(idle_stack : Box<[u8]>) -> Scheduler {
+        const EMPTY : Option<Thread> = None;
+        let mut scheduler = Scheduler {
+            threads         : [EMPTY; MAX_THREADS],
+            current         : 0,
+            idle            : 0,
+            slice_remaining : TIME_SLICE,
+            next_id         : 0
+        };
+
+        //Whatever is running now becomes the boot thread; its context is
+        //filled in the first time it is switched away from.
+        let boot_id = scheduler.allocate_id();
+        scheduler.threads[0] = Some(Thread {
+            id       : boot_id,
+            priority : Priority::Normal,
+            state    : ThreadState::Running,
+            detached : true,
+            entry    : None,
+            rsp      : 0,
+            _stack   : None
+        });
+
+        let (idle, _) = scheduler
+            .add_thread(Priority::Low, Box::new(|| loop { x86_64::instructions::hlt(); }), idle_stack)
+            .expect("No slot for the idle thread");
+        scheduler.idle = idle;
+        scheduler
+    }
+
+    fn allocate_id(&mut self) -> ThreadId {
+        let id = ThreadId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Creates a ready thread that starts in `super::thread_entry`. The stack
+    /// is allocated by the caller so the heap isn't touched under our lock.
+    pub(super) fn add_thread(&mut self, priority : Priority,
+        entry : Box<dyn FnOnce() + Send + 'static>, mut stack : Box<[u8]>) -> Option<(usize, ThreadId)>
+    {
+        let slot = self.threads.iter().position(|t| t.is_none())?;
+
+        let rsp = context::initial_stack(&mut stack, super::thread_entry);
+
+        let id = self.allocate_id();
+        self.threads[slot] = Some(Thread {
+            id       : id,
+            priority : priority,
+            state    : ThreadState::Ready,
+            detached : false,
+            entry    : Some(entry),
+            rsp      : rsp,
+            _stack   : Some(stack)
+        });
+        Some((slot, id))
+    }
+
+    pub(super) fn current_id(&self) -> ThreadId {
+        self.threads[self.current].as_ref().expect("Current thread has no slot").id
+    }
+
+    pub(super) fn current(&mut self) -> &mut Thread {
+        self.threads[self.current].as_mut().expect("Current thread has no slot")
+    }
+
+    pub(super) fn find(&mut self, id : ThreadId) -> Option<&mut Thread> {
+        self.threads.iter_mut()
+            .filter_map(|t| t.as_mut())
+            .find(|t| t.id == id)
+    }
+
+    /// Removes a finished thread, handing it back so the caller can free its
+    /// stack outside of the scheduler lock.
+    pub(super) fn take_finished(&mut self, id : ThreadId) -> Option<Thread> {
+        let slot = self.threads.iter().position(|t| match t {
+            Some(t) => t.id == id && t.state == ThreadState::Finished,
+            None => false
+        })?;
+        self.threads[slot].take()
+    }
+
+    /// Takes one finished thread nobody is going to join.
+    pub(super) fn take_detached(&mut self) -> Option<Thread> {
+        let current = self.current;
+        let slot = self.threads.iter().enumerate().position(|(i, t)| match t {
+            Some(t) => i != current && t.detached && t.state == ThreadState::Finished,
+            None => false
+        })?;
+        self.threads[slot].take()
+    }
+
+    pub(super) fn wake(&mut self, id : ThreadId) {
+        if let Some(thread) = self.find(id) {
+            if let ThreadState::Sleeping(_) = thread.state {
+                thread.state = ThreadState::Ready;
+            }
+        }
+    }
+
+    /// Marks the current thread finished and readies anything joining it.
+    pub(super) fn finish_current(&mut self) {
+        let id = self.current().id;
+        self.current().state = ThreadState::Finished;
+        for thread in self.threads.iter_mut().filter_map(|t| t.as_mut()) {
+            if thread.state == ThreadState::Joining(id) {
+                thread.state = ThreadState::Ready;
+            }
+        }
+    }
+
+    fn on_tick(&mut self) -> bool {
+        let ticks = time::ticks();
+        let mut woke = None;
+        for thread in self.threads.iter_mut().filter_map(|t| t.as_mut()) {
+            if let ThreadState::Sleeping(until) = thread.state {
+                if ticks >= until {
+                    thread.state = ThreadState::Ready;
+                    woke = woke.max(Some(thread.priority));
+                }
+            }
+        }
+
+        if self.slice_remaining > 0 {
+            self.slice_remaining -= 1;
+        }
+
+        //Preempt when the slice is used up, or straight away if something
+        //more important just woke up.
+        let current_priority = self.current().priority;
+        self.slice_remaining == 0
+            || woke.map_or(false, |p| p > current_priority)
+            || self.current == self.idle
+    }
+
+    /// Saves `rsp` as the current thread's context, picks the next thread
+    /// and returns the stack pointer to resume it from.
+    fn switch(&mut self, rsp : u64) -> u64 {
+        {
+            let current = self.current();
+            current.rsp = rsp;
+            if current.state == ThreadState::Running {
+                current.state = ThreadState::Ready;
+            }
+        }
+
+        let next = self.pick_next();
+        self.current = next;
+        self.slice_remaining = TIME_SLICE;
+
+        let thread = self.current();
+        thread.state = ThreadState::Running;
+        thread.rsp
+    }
+
+    fn pick_next(&self) -> usize {
+        let mut best : Option<(usize, Priority)> = None;
+
+        for offset in 1..=MAX_THREADS {
+            let index = (self.current + offset) % MAX_THREADS;
+            if index == self.idle {
+                continue;
+            }
+            if let Some(thread) = &self.threads[index] {
+                if thread.state != ThreadState::Ready {
+                    continue;
+                }
+                if best.map_or(true, |(_, priority)| thread.priority > priority) {
+                    best = Some((index, thread.priority));
+                }
+            }
+        }
+
+        best.map_or(self.idle, |(index, _)| index)
+    }
+}
+
+pub(super) static SCHEDULER : Mutex<Option<Scheduler>> = Mutex::new(None);
+
+/// Called from the timer entry point with interrupts disabled.
+pub(super) extern "C" fn timer_switch(rsp : u64) -> u64 {
+    time::tick();
+
+    let next = match SCHEDULER.try_lock() {
+        Some(mut guard) => match guard.as_mut() {
+            Some(scheduler) => {
+                let deferred = DEFERRED_WAKE.swap(NO_THREAD, Ordering::AcqRel);
+                if deferred != NO_THREAD {
+                    scheduler.wake(ThreadId(deferred));
+                }
+                if scheduler.on_tick() { scheduler.switch(rsp) } else { rsp }
+            }
+            None => rsp
+        },
+        None => rsp
+    };
+
+    irq::chain_timer();
+    pics::clear_interrupt(pics::InterruptIndex::Timer);
+    next
+}
+
+/// Called from the yield entry point, reached through `int YIELD_VECTOR`.
+pub(super) extern "C" fn yield_switch(rsp : u64) -> u64 {
+    match SCHEDULER.try_lock() {
+        Some(mut guard) => match guard.as_mut() {
+            Some(scheduler) => scheduler.switch(rsp),
+            None => rsp
+        },
+        None => rsp
+    }
+}
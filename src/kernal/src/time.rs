@@ -0,0 +1,98 @@
+//time.rs
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+use crate::pit;
+use crate::thread;
+
+//Until `set_tick_rate` runs the PIT keeps the BIOS reload value of 0, which
+//the PIT treats as 65536.
+const DEFAULT_RELOAD : u32 = 65536;
+
+static TICKS : AtomicU64 = AtomicU64::new(0);
+
+/// PIT input clock cycles elapsed, summed per tick so changing the tick
+/// rate doesn't skew the time already counted.
+static CYCLES : AtomicU64 = AtomicU64::new(0);
+
+static RELOAD : AtomicU32 = AtomicU32::new(DEFAULT_RELOAD);
+
+/// Called once per timer interrupt.
+pub(crate) fn tick() {
+    CYCLES.fetch_add(RELOAD.load(Ordering::Relaxed) as u64, Ordering::Relaxed);
+    TICKS.fetch_add(1, Ordering::Release);
+}
+
+/// Records the reload value the PIT was just programmed with.
+pub(crate) fn set_reload_value(reload : u16) {
+    let reload = if reload == 0 { DEFAULT_RELOAD } else { reload as u32 };
+    RELOAD.store(reload, Ordering::Relaxed);
+}
+
+/// PIT cycles per tick, whichever timer is actually generating the ticks.
+pub(crate) fn reload_value() -> u32 {
+    RELOAD.load(Ordering::Relaxed)
+}
+
+/// Timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Acquire)
+}
+
+/// Time between timer interrupts at the current tick rate.
+pub fn tick_period() -> Duration {
+    cycles_to_duration(RELOAD.load(Ordering::Relaxed) as u64)
+}
+
+/// Time since boot, as counted by the timer interrupt. Monotonic, with the
+/// resolution of one tick.
+pub fn uptime() -> Duration {
+    cycles_to_duration(CYCLES.load(Ordering::Relaxed))
+}
+
+fn cycles_to_duration(cycles : u64) -> Duration {
+    let nanos = cycles as u128 * 1_000_000_000 / pit::FREQUENCY as u128;
+    Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32)
+}
+
+pub fn sleep(ms : u64) {
+    sleep_until(uptime() + Duration::from_millis(ms));
+}
+
+/// Sleeps until `uptime()` has reached `deadline`. Other threads run in the
+/// meantime, and other interrupts don't cut the wait short.
+pub fn sleep_until(deadline : Duration) {
+    loop {
+        let now = uptime();
+        if now >= deadline {
+            return;
+        }
+        let period = tick_period().as_nanos();
+        let remaining = (deadline - now).as_nanos();
+        let ticks = ((remaining + period - 1) / period) as u64;
+        sleep_ticks(ticks);
+    }
+}
+
+/// Sleeps for at least `ticks` timer interrupts.
+pub fn sleep_ticks(ticks : u64) {
+    assert!(interrupts::are_enabled(), "Sleeping with interrupts disabled would never wake");
+
+    let until = self::ticks() + ticks;
+    while self::ticks() < until {
+        if !thread::sleep_until_tick(until) {
+            x86_64::instructions::hlt();
+        }
+    }
+}
+
+#[test_case]
+fn test_cycles_to_duration() {
+    assert_eq!(cycles_to_duration(0), Duration::from_secs(0));
+    assert_eq!(cycles_to_duration(pit::FREQUENCY as u64), Duration::from_secs(1));
+    assert_eq!(cycles_to_duration(pit::FREQUENCY as u64 * 90), Duration::from_secs(90));
+    //1193 cycles, the reload value for 1 kHz
+    assert_eq!(cycles_to_duration(1193).as_micros(), 999);
+}
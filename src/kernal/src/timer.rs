@@ -0,0 +1,317 @@
+//timer.rs
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::keyboard;
+use crate::serial;
+use crate::speaker;
+use crate::thread::{self, Priority, ThreadId};
+use crate::time;
+
+/// Slots in the wheel. Timers further out than this wait in their slot for
+/// the wheel to come round again.
+const WHEEL_SIZE : usize = 256;
+
+type Callback = Box<dyn FnMut() + Send + 'static>;
+
+struct Entry {
+    deadline  : u64,
+    period    : Option<u64>,
+    callback  : Callback,
+    cancelled : Arc<AtomicBool>
+}
+
+/// Hashed timing wheel indexed by tick. Expired timers are handed to the
+/// timer thread, which runs their callbacks with interrupts enabled and no
+/// locks held, so callbacks are free to add or cancel timers.
+struct Wheel {
+    slots          : Vec<Vec<Entry>>,
+    processed      : u64,
+    thread         : Option<ThreadId>,
+    sleeping_until : u64
+}
+
+impl Wheel {
+    fn new() -> Wheel {
+        let mut slots = Vec::with_capacity(WHEEL_SIZE);
+        slots.resize_with(WHEEL_SIZE, Vec::new);
+        Wheel {
+            slots          : slots,
+            processed      : time::ticks(),
+            thread         : None,
+            sleeping_until : u64::MAX
+        }
+    }
+
+    fn insert(&mut self, mut entry : Entry) {
+        //Anything already due fires on the next pass.
+        if entry.deadline <= self.processed {
+            entry.deadline = self.processed + 1;
+        }
+        let deadline = entry.deadline;
+        self.slots[deadline as usize % WHEEL_SIZE].push(entry);
+
+        if deadline < self.sleeping_until {
+            self.sleeping_until = deadline;
+            if let Some(id) = self.thread {
+                thread::wake(id);
+            }
+        }
+    }
+
+    /// Removes every timer due at or before `now`.
+    fn expire(&mut self, now : u64) -> Vec<Entry> {
+        let mut due = Vec::new();
+        let span = core::cmp::min(now.saturating_sub(self.processed), WHEEL_SIZE as u64);
+
+        for tick in (now - span + 1)..=now {
+            let slot = &mut self.slots[tick as usize % WHEEL_SIZE];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].deadline <= now {
+                    due.push(slot.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        if now > self.processed {
+            self.processed = now;
+        }
+        due
+    }
+
+    fn remove(&mut self, cancelled : &Arc<AtomicBool>) {
+        for slot in self.slots.iter_mut() {
+            slot.retain(|entry| !Arc::ptr_eq(&entry.cancelled, cancelled));
+        }
+    }
+
+    fn next_deadline(&self) -> Option<u64> {
+        self.slots.iter()
+            .flat_map(|slot| slot.iter())
+            .filter(|entry| !entry.cancelled.load(Ordering::Acquire))
+            .map(|entry| entry.deadline)
+            .min()
+    }
+
+    fn len(&self) -> usize {
+        self.slots.iter().map(|slot| slot.len()).sum()
+    }
+}
+
+static WHEEL : Mutex<Option<Wheel>> = Mutex::new(None);
+
+//The timer thread's id, for `nudge`, which can't take the wheel's lock.
+//u64::MAX until `init` has started it.
+static THREAD : AtomicU64 = AtomicU64::new(u64::MAX);
+//Set by `nudge`, and checked before the timer thread blocks, because the
+//deferred wake is dropped if the thread hasn't gone to sleep yet.
+static WORK_PENDING : AtomicBool = AtomicBool::new(false);
+
+/// Cancels the timer it was returned for. Dropping the handle leaves the
+/// timer running.
+#[derive(Debug, Clone)]
+pub struct TimerHandle {
+    cancelled : Arc<AtomicBool>
+}
+
+impl TimerHandle {
+    /// Takes the timer out of the wheel, so the timer thread isn't woken for
+    /// it. A callback that is already running finishes.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+        without_interrupts(|| {
+            if let Some(wheel) = WHEEL.lock().as_mut() {
+                wheel.remove(&self.cancelled);
+            }
+        });
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// Starts the timer thread. Must run after `thread::init`.
+pub fn init() {
+    serial::print!("Initialising Timers...");
+    without_interrupts(|| {
+        *WHEEL.lock() = Some(Wheel::new());
+    });
+
+    let handle = thread::spawn_with_priority(Priority::High, run);
+    let id = handle.id();
+    //The timer thread runs for as long as the kernel does.
+    drop(handle);
+    without_interrupts(|| {
+        if let Some(wheel) = WHEEL.lock().as_mut() {
+            wheel.thread = Some(id);
+        }
+    });
+    THREAD.store(id.as_u64(), Ordering::Release);
+    serial::println!("[OK]");
+}
+
+pub fn is_initialised() -> bool {
+    without_interrupts(|| WHEEL.lock().is_some())
+}
+
+/// Timers waiting to fire, not counting any whose callback is running.
+pub fn pending_timers() -> usize {
+    without_interrupts(|| WHEEL.lock().as_ref().map_or(0, |wheel| wheel.len()))
+}
+
+/// Has the timer thread look for deferred work, like a pending bell, by the
+/// next tick. Takes no locks, so it is safe from anywhere. Returns false if
+/// the timer thread isn't running yet.
+pub(crate) fn nudge() -> bool {
+    let id = THREAD.load(Ordering::Acquire);
+    if id == u64::MAX {
+        return false;
+    }
+    WORK_PENDING.store(true, Ordering::Release);
+    thread::wake_on_next_tick(thread::ThreadId::from_u64(id));
+    true
+}
+
+/// Calls `callback` once, `ms` milliseconds from now.
+pub fn after<F>(ms : u64, callback : F) -> TimerHandle where F : FnOnce() + Send + 'static {
+    let mut callback = Some(callback);
+    add(ms_to_ticks(ms), None, Box::new(move || {
+        if let Some(callback) = callback.take() {
+            callback();
+        }
+    }))
+}
+
+/// Calls `callback` every `ms` milliseconds until the handle is cancelled.
+pub fn every<F>(ms : u64, callback : F) -> TimerHandle where F : FnMut() + Send + 'static {
+    let period = ms_to_ticks(ms);
+    add(period, Some(period), Box::new(callback))
+}
+
+fn add(delay : u64, period : Option<u64>, callback : Callback) -> TimerHandle {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let entry = Entry {
+        deadline  : time::ticks() + delay,
+        period    : period,
+        callback  : callback,
+        cancelled : cancelled.clone()
+    };
+    without_interrupts(|| {
+        WHEEL.lock().as_mut().expect("Timers not initialised").insert(entry);
+    });
+    TimerHandle { cancelled : cancelled }
+}
+
+/// Rounds up to whole ticks, never less than one.
+fn ms_to_ticks(ms : u64) -> u64 {
+    let period = time::tick_period().as_nanos();
+    let nanos = ms as u128 * 1_000_000;
+    core::cmp::max((nanos + period - 1) / period, 1) as u64
+}
+
+fn run() {
+    loop {
+        WORK_PENDING.store(false, Ordering::Release);
+        keyboard::reboot_if_requested();
+        speaker::play_pending_bell();
+
+        let now = time::ticks();
+        let due = without_interrupts(|| {
+            WHEEL.lock().as_mut().map(|wheel| wheel.expire(now)).unwrap_or_default()
+        });
+
+        for mut entry in due {
+            if entry.cancelled.load(Ordering::Acquire) {
+                continue;
+            }
+            (entry.callback)();
+
+            if let Some(period) = entry.period {
+                if entry.cancelled.load(Ordering::Acquire) {
+                    continue;
+                }
+                entry.deadline += period;
+                if entry.deadline <= now {
+                    //Fell behind, skip the missed periods rather than
+                    //firing them all at once.
+                    entry.deadline = now + period;
+                }
+                without_interrupts(|| {
+                    WHEEL.lock().as_mut().expect("Timers not initialised").insert(entry);
+                });
+            }
+        }
+
+        let blocked = without_interrupts(|| {
+            let mut guard = WHEEL.lock();
+            let wheel = guard.as_mut().expect("Timers not initialised");
+            let next = wheel.next_deadline().unwrap_or(u64::MAX);
+            wheel.sleeping_until = next;
+            //A nudge can't land between this check and blocking, interrupts are off.
+            next > time::ticks() && !WORK_PENDING.load(Ordering::Acquire) && thread::block_until_tick(next)
+        });
+        if blocked {
+            thread::yield_now();
+        }
+    }
+}
+
+struct SleepState {
+    done  : AtomicBool,
+    waker : AtomicWaker
+}
+
+/// Future that completes `ms` milliseconds after `sleep` was called, for
+/// async tasks that need to wait without blocking the executor.
+pub struct Sleep {
+    state  : Arc<SleepState>,
+    handle : TimerHandle
+}
+
+pub fn sleep(ms : u64) -> Sleep {
+    let state = Arc::new(SleepState {
+        done  : AtomicBool::new(false),
+        waker : AtomicWaker::new()
+    });
+    let timer_state = state.clone();
+    let handle = after(ms, move || {
+        timer_state.done.store(true, Ordering::Release);
+        timer_state.waker.wake();
+    });
+    Sleep { state : state, handle : handle }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self : Pin<&mut Self>, cx : &mut Context) -> Poll<()> {
+        if self.state.done.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        self.state.waker.register(cx.waker());
+        if self.state.done.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.handle.cancel();
+    }
+}
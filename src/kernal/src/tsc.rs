@@ -0,0 +1,189 @@
+//tsc.rs
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::pit;
+use crate::serial;
+use crate::time;
+
+//CPUID leaf 1 EDX
+static CPUID_TSC : u32 = 1 << 4;
+//CPUID leaf 0x8000_0007 EDX, the TSC runs at a constant rate in all P/C states.
+static CPUID_INVARIANT_TSC : u32 = 1 << 8;
+
+/// PIT cycles counted per calibration run, about 10 ms.
+static CALIBRATION_CYCLES : u16 = 11_932;
+static CALIBRATION_RUNS   : usize = 3;
+
+static TSC_HZ      : AtomicU64  = AtomicU64::new(0);
+static BOOT_TSC    : AtomicU64  = AtomicU64::new(0);
+static INVARIANT   : AtomicBool = AtomicBool::new(false);
+
+pub fn is_present() -> bool {
+    unsafe { __cpuid(1).edx & CPUID_TSC != 0 }
+}
+
+/// Whether the TSC ticks at a constant rate regardless of power states, and
+/// so can be used as a clock.
+pub fn is_invariant() -> bool {
+    unsafe {
+        let max_extended = __cpuid(0x8000_0000).eax;
+        max_extended >= 0x8000_0007 && __cpuid(0x8000_0007).edx & CPUID_INVARIANT_TSC != 0
+    }
+}
+
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Calibrated TSC rate, 0 if there is no usable TSC.
+pub fn frequency() -> u64 {
+    TSC_HZ.load(Ordering::Relaxed)
+}
+
+/// Measures the TSC rate against PIT channel 2. Must run before anything
+/// else uses channel 2, i.e. the speaker.
+pub fn init() {
+    serial::print!("Calibrating TSC...");
+    if !is_present() {
+        serial::println!("[NONE] falling back to the PIT");
+        return;
+    }
+    INVARIANT.store(is_invariant(), Ordering::Relaxed);
+
+    let mut runs = [0u64; CALIBRATION_RUNS];
+    for run in runs.iter_mut() {
+        *run = without_interrupts(calibrate_once);
+    }
+    runs.sort_unstable();
+    let hz = runs[CALIBRATION_RUNS / 2];
+
+    BOOT_TSC.store(read(), Ordering::Relaxed);
+    TSC_HZ.store(hz, Ordering::Relaxed);
+    serial::println!("[OK] {}.{:03} MHz{}",
+        hz / 1_000_000, hz / 1_000 % 1_000,
+        if INVARIANT.load(Ordering::Relaxed) { ", invariant" } else { "" }
+    );
+}
+
+/// Counts TSC cycles over one PIT channel 2 countdown. The start is taken
+/// from a latched count so the time to start the channel doesn't matter.
+fn calibrate_once() -> u64 {
+    pit::start_countdown(CALIBRATION_CYCLES);
+    let start_count = pit::latch_channel_2();
+    let start_tsc = read();
+    while !pit::countdown_finished() {}
+    let end_tsc = read();
+    pit::stop_countdown();
+
+    //The count runs all the way down to zero from where it was latched.
+    let elapsed = start_count as u64;
+    if elapsed == 0 {
+        return 0;
+    }
+    (end_tsc - start_tsc) * pit::FREQUENCY as u64 / elapsed
+}
+
+/// A point in time with nanosecond resolution, for measuring how long things
+/// take. Without a calibrated TSC it falls back to `time::uptime`. A TSC that
+/// isn't invariant may drift with power states, so long spans are only as
+/// good as `is_invariant`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant::at(read(), frequency())
+    }
+
+    fn at(tsc : u64, hz : u64) -> Instant {
+        if hz == 0 {
+            return Instant(time::uptime().as_nanos() as u64);
+        }
+        let cycles = tsc.wrapping_sub(BOOT_TSC.load(Ordering::Relaxed));
+        Instant((cycles as u128 * 1_000_000_000 / hz as u128) as u64)
+    }
+
+    /// Nanoseconds since the TSC was calibrated.
+    pub fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    pub fn duration_since(&self, earlier : Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier : Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration : Duration) -> Instant {
+        Instant(self.0 + duration.as_nanos() as u64)
+    }
+}
+
+/// A point a short time ahead, for bounding busy-waits on hardware. It has to
+/// expire with interrupts off too, when `time::uptime` stands still, so it
+/// counts TSC cycles directly.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    tsc    : Option<u64>,
+    uptime : Duration
+}
+
+impl Deadline {
+    pub fn after(timeout : Duration) -> Deadline {
+        let hz = frequency();
+        let tsc = if hz == 0 {
+            None
+        } else {
+            Some(read() + (timeout.as_nanos() * hz as u128 / 1_000_000_000) as u64)
+        };
+        Deadline { tsc, uptime : time::uptime() + timeout }
+    }
+
+    /// Without a TSC this needs timer interrupts to ever return true.
+    pub fn has_passed(&self) -> bool {
+        match self.tsc {
+            Some(end) => read() >= end,
+            None => time::uptime() >= self.uptime
+        }
+    }
+}
+
+#[test_case]
+fn test_instant_scaling() {
+    let boot = BOOT_TSC.load(Ordering::Relaxed);
+    assert_eq!(Instant::at(boot + 3_000, 1_000).as_nanos(), 3_000_000_000);
+    assert_eq!(Instant::at(boot + 1, 1_000_000_000).as_nanos(), 1);
+}
+
+#[test_case]
+fn test_uptime_fallback() {
+    //Without a rate the cycle count means nothing, so it must be ignored.
+    let instant = Instant::at(u64::MAX, 0);
+    assert!(instant.as_nanos() <= time::uptime().as_nanos() as u64);
+    assert!(Instant::now() >= instant);
+}
+
+#[test_case]
+fn test_deadline() {
+    assert!(Deadline::after(Duration::from_nanos(0)).has_passed());
+    assert!(!Deadline::after(Duration::from_secs(60)).has_passed());
+}
@@ -133,4 +133,20 @@ impl ScreenBuffer {
             panic!("Bounds At [{},{}] is out of range for the screen buffer", x, y);
         }
     }       
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_color_code_packing() {
+    let mut code = ColorCode::new(Color::White, Color::Blue);
+    assert_eq!(code.as_u8(), 0x1F);
+    assert_eq!(code.get_foreground(), Color::White as u8);
+    assert_eq!(code.get_background(), Color::Blue as u8);
+}
+
+#[test_case]
+fn test_color_code_set_background() {
+    let mut code = ColorCode::new(Color::Yellow, Color::Black);
+    code.set_background(Color::Red as u8);
+    assert_eq!(code.get_foreground(), Color::Yellow as u8);
+    assert_eq!(code.get_background(), Color::Red as u8);
+}
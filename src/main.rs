@@ -1,41 +1,44 @@
 #![no_std]
 #![no_main]
-#![feature(panic_info_message)]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 use core::panic::PanicInfo;
-use kernal::terminal::{
-    println,
-    print,
-    clear,
-    error,
-    set_position,
-    set_background
-};  
-
-use kernal::vga::Color;
+use bootloader::{BootInfo, entry_point};
 use kernal::terminal;
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    kernal::init();
+use kernal::task::{Executor, Task, keyboard};
+
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+
+    #[cfg(test)]
+    test_main();
+
     kernal::post();
     kernal::enable_interrupts();
     kernal::set_tick_rate(1000);
     terminal::clear!();
     terminal::update_cursor();
 
+    #[cfg(feature = "crash-demo")]
     kernal::crash();
 
-    loop {
-        kernal::pause_for(1);
-    }
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.run();
 }
 
+#[cfg(not(test))]
 #[panic_handler]
-pub fn panic_handler(_info : &PanicInfo) -> ! {
-    kernal::disable_interrupts();
-    terminal::set_background!(Color::Red as u8);
-    clear!();
-    set_position!(0,0);
-    error!("{}", _info.message().unwrap());
-    loop {}
-}
\ No newline at end of file
+pub fn panic_handler(info : &PanicInfo) -> ! {
+    kernal::panic::handle_panic(info)
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic_handler(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use kernal::acpi;
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+//QEMU's firmware always provides ACPI tables.
+
+#[test_case]
+fn tables_are_found() {
+    let tables = acpi::tables().expect("no ACPI tables");
+    assert!(tables.find(b"APIC").is_some());
+    assert!(tables.find(b"FACP").is_some());
+}
+
+#[test_case]
+fn madt_describes_the_machine() {
+    let madt = acpi::tables().unwrap().madt.as_ref().expect("no MADT");
+    assert!(madt.local_apic_address != 0);
+    assert!(madt.processors.iter().any(|p| p.usable));
+    assert!(!madt.io_apics.is_empty());
+}
+
+#[test_case]
+fn fadt_has_power_management_registers() {
+    let fadt = acpi::tables().unwrap().fadt.as_ref().expect("no FADT");
+    assert!(fadt.pm1a_control_block != 0);
+    assert!(fadt.dsdt != 0);
+}
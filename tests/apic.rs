@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use kernal::{apic, time};
+use kernal::tsc::Instant;
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn apic_is_used_when_present() {
+    assert_eq!(apic::is_enabled(), apic::is_present());
+}
+
+#[test_case]
+fn apic_timer_is_calibrated() {
+    if apic::is_enabled() {
+        assert!(apic::timer_frequency() > 0);
+    }
+}
+
+#[test_case]
+fn timer_ticks_at_the_requested_rate() {
+    let start_ticks = time::ticks();
+    let start = Instant::now();
+    time::sleep(100);
+    let ticks = time::ticks() - start_ticks;
+    let elapsed = start.elapsed().as_millis() as u64;
+
+    //1 kHz, so roughly one tick per millisecond.
+    assert!(ticks * 10 >= elapsed * 8, "{} ticks in {} ms", ticks, elapsed);
+    assert!(ticks * 10 <= elapsed * 12, "{} ticks in {} ms", ticks, elapsed);
+}
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use kernal::terminal::println;
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_println() {
+    println!("test_println output");
+}
+
+#[test_case]
+fn test_println_many() {
+    for i in 0..200 {
+        println!("test_println_many output {}", i);
+    }
+}
+
+#[test_case]
+fn test_breakpoint_exception() {
+    kernal::breakpoint!();
+}
@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use kernal::allocator::HEAP_SIZE;
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+//A bump allocator can't reuse memory while anything is still alive.
+#[cfg(not(feature = "bump"))]
+#[test_case]
+fn many_boxes_long_lived() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn stats_track_allocations() {
+    let before = kernal::allocator::stats();
+    let boxed = Box::new([0u8; 64]);
+    let during = kernal::allocator::stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert!(during.used > before.used);
+    drop(boxed);
+    assert_eq!(kernal::allocator::stats().allocations, before.allocations);
+}
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use bootloader::{BootInfo, entry_point};
+use spin::Mutex;
+use kernal::irq::{self, IrqError, IrqHandler};
+use kernal::{rtc, time};
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+static FIRED       : AtomicUsize = AtomicUsize::new(0);
+static RTC_HANDLER : Mutex<Option<IrqHandler>> = Mutex::new(None);
+
+fn count_interrupt() {
+    FIRED.fetch_add(1, Ordering::SeqCst);
+    //Let the RTC acknowledge the interrupt too, or it never raises another.
+    if let Some(handler) = *RTC_HANDLER.lock() {
+        handler();
+    }
+}
+
+/// Frees the RTC's line, whose periodic interrupt is easy to trigger.
+fn take_rtc_line() {
+    *RTC_HANDLER.lock() = irq::handler(rtc::RTC_IRQ);
+    irq::unregister(rtc::RTC_IRQ).unwrap();
+}
+
+fn return_rtc_line() {
+    irq::unregister(rtc::RTC_IRQ).unwrap();
+    let handler = RTC_HANDLER.lock().take().expect("RTC line wasn't taken");
+    irq::register(rtc::RTC_IRQ, handler).unwrap();
+}
+
+static TICKS : AtomicUsize = AtomicUsize::new(0);
+
+fn count_tick() {
+    TICKS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn timer_handler_is_chained_after_the_scheduler() {
+    irq::register(0, count_tick).unwrap();
+    time::sleep(20);
+    irq::unregister(0).unwrap();
+
+    let ticks = TICKS.load(Ordering::SeqCst);
+    assert!(ticks >= 10);
+    //The scheduler keeps ticking without it.
+    time::sleep(10);
+    assert_eq!(TICKS.load(Ordering::SeqCst), ticks);
+    assert_eq!(irq::mask(0), Err(IrqError::Reserved(0)));
+}
+
+#[test_case]
+fn lines_cannot_be_registered_twice() {
+    assert!(irq::is_registered(rtc::RTC_IRQ));
+    assert_eq!(irq::register(rtc::RTC_IRQ, count_interrupt), Err(IrqError::AlreadyRegistered(rtc::RTC_IRQ)));
+}
+
+#[test_case]
+fn registered_handler_runs() {
+    take_rtc_line();
+    irq::register(rtc::RTC_IRQ, count_interrupt).unwrap();
+
+    let before = irq::count(rtc::RTC_IRQ);
+    let fired = FIRED.load(Ordering::SeqCst);
+    rtc::enable_periodic(6);
+    time::sleep(50);
+    rtc::disable_periodic();
+
+    assert!(irq::count(rtc::RTC_IRQ) > before);
+    assert!(FIRED.load(Ordering::SeqCst) > fired);
+    return_rtc_line();
+}
+
+#[test_case]
+fn unregistered_lines_are_free_again() {
+    take_rtc_line();
+    assert!(!irq::is_registered(rtc::RTC_IRQ));
+    assert!(irq::register(rtc::RTC_IRQ, count_interrupt).is_ok());
+    return_rtc_line();
+    assert!(irq::is_registered(rtc::RTC_IRQ));
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use kernal::{input, mouse, vga};
+use kernal::mouse::MouseButton;
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+//QEMU always attaches a PS/2 mouse, but nothing moves it during the tests.
+
+#[test_case]
+fn mouse_is_initialised() {
+    assert!(mouse::is_present());
+}
+
+#[test_case]
+fn pointer_starts_on_screen() {
+    let (width, height) = vga::screen_dimensions();
+    let (x, y) = mouse::position().unwrap();
+    assert!(x < width && y < height);
+}
+
+#[test_case]
+fn no_buttons_are_pressed() {
+    assert!(!mouse::is_pressed(MouseButton::Left));
+    assert!(!mouse::is_pressed(MouseButton::Right));
+    assert_eq!(input::try_read_event(), None);
+}
+
+#[test_case]
+fn pointer_can_be_hidden_and_shown() {
+    let (x, y) = mouse::position().unwrap();
+    let screen = vga::ScreenBuffer::new();
+    mouse::hide_pointer();
+    let plain = (screen.get_fg_color(x, y), screen.get_bg_color(x, y));
+    assert_ne!(plain.0, plain.1);
+
+    mouse::show_pointer();
+    assert_eq!((screen.get_fg_color(x, y), screen.get_bg_color(x, y)), (plain.1, plain.0 & 0x07));
+
+    mouse::hide_pointer();
+    assert_eq!((screen.get_fg_color(x, y), screen.get_bg_color(x, y)), plain);
+    mouse::show_pointer();
+}
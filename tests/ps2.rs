@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use kernal::keyboard::{self, ScancodeSet};
+use kernal::ps2;
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+//QEMU emulates an 8042 with a keyboard and a mouse attached.
+
+#[test_case]
+fn devices_are_detected() {
+    let controller = ps2::controller().expect("PS/2 controller not initialised");
+    assert!(controller.dual_channel);
+    assert!(controller.first.map_or(false, |device| device.is_keyboard()));
+    assert!(controller.second.map_or(false, |device| device.is_mouse()));
+}
+
+#[test_case]
+fn translation_matches_the_scancode_set() {
+    let controller = ps2::controller().unwrap();
+    if controller.translation {
+        assert_eq!(keyboard::scancode_set(), ScancodeSet::Set1);
+    }
+}
+
+#[test_case]
+fn keyboard_acknowledges_commands_with_interrupts_enabled() {
+    assert_eq!(keyboard::set_typematic(ps2::DEFAULT_TYPEMATIC_RATE, ps2::DEFAULT_TYPEMATIC_DELAY), Ok(()));
+    assert_eq!(keyboard::set_leds_state(keyboard::state().leds()), Ok(()));
+}
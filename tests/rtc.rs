@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use kernal::{rtc, time};
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn rtc_reads_a_plausible_date() {
+    let now = rtc::read_time();
+    assert!(now.year >= 2020);
+    assert!((1..=12).contains(&now.month));
+    assert!((1..=31).contains(&now.day));
+    assert!(now.hour < 24 && now.minute < 60 && now.second < 60);
+}
+
+#[test_case]
+fn now_follows_the_rtc() {
+    let rtc_seconds = rtc::read_time().to_unix();
+    let now_seconds = rtc::now().to_unix();
+    assert!(now_seconds + 2 >= rtc_seconds && now_seconds <= rtc_seconds + 2);
+}
+
+#[test_case]
+fn periodic_interrupt_fires() {
+    let before = rtc::periodic_ticks();
+    //1024 Hz
+    rtc::enable_periodic(6);
+    time::sleep(50);
+    rtc::disable_periodic();
+    assert!(rtc::periodic_ticks() > before);
+}
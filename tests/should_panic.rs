@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::testing::should_panic("should_panic::should_fail", should_fail);
+}
+
+fn should_fail() {
+    assert_eq!(0, 1);
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::should_panic_handler(info)
+}
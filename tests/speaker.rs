@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use kernal::speaker::{self, Note};
+use kernal::{time, timer};
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn melody_plays_in_the_background() {
+    speaker::play(&[Note::new(440, 20), Note::rest(20), Note::new(660, 20)]);
+    assert!(speaker::is_playing());
+
+    time::sleep(200);
+    assert!(!speaker::is_playing());
+}
+
+#[test_case]
+fn clearing_the_queue_ends_the_melody() {
+    speaker::play(&[Note::new(440, 20); 50]);
+    speaker::clear_queue();
+
+    time::sleep(100);
+    assert!(!speaker::is_playing());
+}
+
+#[test_case]
+fn bell_does_not_block() {
+    let start = time::uptime();
+    kernal::terminal::print!("\x07");
+    assert!(time::uptime() - start < core::time::Duration::from_millis(speaker::BELL_DURATION));
+    time::sleep(speaker::BELL_DURATION * 2);
+    assert!(!speaker::is_playing());
+}
+
+#[test_case]
+fn bell_is_played_by_the_timer_thread() {
+    kernal::terminal::print!("\x07");
+    time::sleep(speaker::BELL_DURATION / 2);
+    assert!(speaker::is_playing());
+    time::sleep(speaker::BELL_DURATION * 2);
+    assert!(!speaker::is_playing());
+}
+
+#[test_case]
+fn bell_rung_by_the_timer_thread_itself_plays() {
+    //The timer thread is running when it nudges itself, so only the pending
+    //work flag stops it going back to sleep.
+    timer::after(1, speaker::bell);
+    time::sleep(speaker::BELL_DURATION / 2);
+    assert!(speaker::is_playing());
+    time::sleep(speaker::BELL_DURATION * 2);
+    assert!(!speaker::is_playing());
+}
@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+use bootloader::{BootInfo, entry_point};
+use lazy_static::lazy_static;
+use volatile::Volatile;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use kernal::serial;
+use kernal::testing::{exit_qemu, QemuExitCode};
+
+entry_point!(main);
+
+fn main(_boot_info : &'static BootInfo) -> ! {
+    serial::print!("stack_overflow::stack_overflow...\t");
+
+    kernal::gdt::init_gdt();
+    TEST_IDT.load();
+
+    stack_overflow();
+
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    //Stops the recursion from being turned into a loop.
+    Volatile::new(0).read();
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault.set_handler_fn(test_double_fault_handler)
+                .set_stack_index(kernal::gdt::DOUBLE_FAULT_FIRST_INDEX);
+        }
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame : InterruptStackFrame, _error_code : u64) -> !
+{
+    let marker = 0u8;
+    let stack_pointer = &marker as *const u8 as u64;
+    let (bottom, top) = kernal::gdt::double_fault_stack_bounds();
+
+    if stack_pointer >= bottom.as_u64() && stack_pointer < top.as_u64() {
+        serial::println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial::println!("[failed]");
+        serial::println!("Handler ran at 0x{:x}, outside the IST stack 0x{:x}..0x{:x}",
+            stack_pointer, bottom.as_u64(), top.as_u64());
+        exit_qemu(QemuExitCode::Failed);
+    }
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
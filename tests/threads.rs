@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use bootloader::{BootInfo, entry_point};
+use kernal::{thread, time};
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn spawn_and_join() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let handles : Vec<_> = (0..8).map(|_| {
+        let counter = counter.clone();
+        thread::spawn(move || { counter.fetch_add(1, Ordering::SeqCst); })
+    }).collect();
+
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 8);
+}
+
+#[test_case]
+fn threads_are_preempted() {
+    //Neither side yields, sleeps or joins while waiting on the other, so the
+    //worker only gets to run if the timer switches to it.
+    let running = Arc::new(AtomicBool::new(false));
+    let stop = Arc::new(AtomicBool::new(false));
+    let worker = {
+        let (running, stop) = (running.clone(), stop.clone());
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                running.store(true, Ordering::SeqCst);
+            }
+        })
+    };
+
+    let deadline = time::ticks() + 1000;
+    while !running.load(Ordering::SeqCst) && time::ticks() < deadline {
+        core::hint::spin_loop();
+    }
+    let preempted = running.load(Ordering::SeqCst);
+
+    stop.store(true, Ordering::SeqCst);
+    worker.join();
+    assert!(preempted, "the worker never ran while this thread was busy");
+}
+
+#[test_case]
+fn sleeping_thread_lets_others_run() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let sleeper = {
+        let counter = counter.clone();
+        thread::spawn(move || {
+            time::sleep_ticks(50);
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+    };
+    let worker = {
+        let counter = counter.clone();
+        thread::spawn(move || { counter.fetch_add(10, Ordering::SeqCst); })
+    };
+
+    worker.join();
+    assert_eq!(counter.load(Ordering::SeqCst), 10);
+    sleeper.join();
+    assert_eq!(counter.load(Ordering::SeqCst), 11);
+}
+
+#[test_case]
+fn detached_threads_are_reaped() {
+    //More than MAX_THREADS detached threads only fit if finished ones are freed.
+    for _ in 0..(thread::scheduler::MAX_THREADS * 2) {
+        drop(thread::spawn(|| {}));
+        thread::yield_now();
+    }
+}
@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::time::Duration;
+use bootloader::{BootInfo, entry_point};
+use kernal::time;
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn uptime_is_monotonic() {
+    let mut last = time::uptime();
+    for _ in 0..100 {
+        let now = time::uptime();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn tick_period_follows_tick_rate() {
+    assert_eq!(time::tick_period().as_micros(), 999);
+}
+
+#[test_case]
+fn sleep_waits_at_least_as_long_as_asked() {
+    let start = time::uptime();
+    time::sleep(20);
+    assert!(time::uptime() - start >= Duration::from_millis(20));
+}
+
+#[test_case]
+fn sleep_until_past_deadline_returns() {
+    time::sleep_until(Duration::from_secs(0));
+}
+
+#[test_case]
+fn sleep_ticks_counts_ticks() {
+    let start = time::ticks();
+    time::sleep_ticks(5);
+    assert!(time::ticks() >= start + 5);
+}
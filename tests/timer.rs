@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use bootloader::{BootInfo, entry_point};
+use kernal::{time, timer};
+use kernal::task::{Executor, Task};
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn one_shot_fires_once() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    timer::after(10, move || { counter.fetch_add(1, Ordering::SeqCst); });
+
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+    time::sleep(50);
+    assert_eq!(fired.load(Ordering::SeqCst), 1);
+}
+
+#[test_case]
+fn periodic_fires_until_cancelled() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    let handle = timer::every(5, move || { counter.fetch_add(1, Ordering::SeqCst); });
+
+    time::sleep(100);
+    handle.cancel();
+    let count = fired.load(Ordering::SeqCst);
+    assert!(count >= 2);
+
+    time::sleep(50);
+    assert_eq!(fired.load(Ordering::SeqCst), count);
+}
+
+#[test_case]
+fn cancelled_one_shot_never_fires() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    let handle = timer::after(10, move || { counter.fetch_add(1, Ordering::SeqCst); });
+    handle.cancel();
+
+    time::sleep(50);
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+}
+
+#[test_case]
+fn cancelled_timers_leave_the_wheel() {
+    let pending = timer::pending_timers();
+    let handle = timer::after(10_000, || {});
+    assert_eq!(timer::pending_timers(), pending + 1);
+    handle.cancel();
+    assert_eq!(timer::pending_timers(), pending);
+}
+
+#[test_case]
+fn sleep_future_completes_on_the_executor() {
+    let woken = Arc::new(AtomicBool::new(false));
+    let flag = woken.clone();
+    let start = time::uptime();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        timer::sleep(20).await;
+        flag.store(true, Ordering::SeqCst);
+    }));
+    executor.run_until_complete();
+
+    assert!(woken.load(Ordering::SeqCst));
+    assert!(time::uptime() - start >= core::time::Duration::from_millis(20));
+}
+
+#[test_case]
+fn timers_beyond_one_turn_of_the_wheel() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    timer::after(600, move || { counter.fetch_add(1, Ordering::SeqCst); });
+
+    time::sleep(300);
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+    time::sleep(400);
+    assert_eq!(fired.load(Ordering::SeqCst), 1);
+}
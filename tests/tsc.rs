@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernal::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::time::Duration;
+use bootloader::{BootInfo, entry_point};
+use kernal::time;
+use kernal::tsc::{self, Instant};
+
+entry_point!(main);
+
+fn main(boot_info : &'static BootInfo) -> ! {
+    kernal::init(boot_info);
+    kernal::enable_interrupts();
+    kernal::set_tick_rate(1000);
+    test_main();
+    kernal::spin!();
+}
+
+#[panic_handler]
+fn panic(info : &PanicInfo) -> ! {
+    kernal::testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn tsc_is_calibrated() {
+    if tsc::is_present() {
+        //Anything slower than 100 MHz means calibration went wrong.
+        assert!(tsc::frequency() > 100_000_000);
+    }
+}
+
+#[test_case]
+fn instants_are_monotonic() {
+    let mut last = Instant::now();
+    for _ in 0..1000 {
+        let now = Instant::now();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn instant_agrees_with_the_pit() {
+    let start = Instant::now();
+    time::sleep(50);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(45), "measured {:?}", elapsed);
+    assert!(elapsed <= Duration::from_millis(100), "measured {:?}", elapsed);
+}
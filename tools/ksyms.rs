@@ -0,0 +1,263 @@
+//tools/ksyms.rs
+//
+//Post-link step: reads the function symbols out of the kernel ELF and
+//patches a sorted address -> name table into its `.ksyms` section, which
+//`kernal::symbols` reads at runtime to symbolise backtraces.
+//
+//Deliberately dependency free so `tools/link.sh` can build it with plain
+//`rustc`, out of reach of the kernel's `.cargo/config.toml`.
+//
+//Usage: ksyms <kernel-elf>
+
+use std::{env, fs, process};
+
+//Must match the header in src/kernal/src/symbols.rs
+const KSYMS_MAGIC : &[u8; 4] = b"KSYM";
+const HEADER_SIZE : usize = 16;
+const ENTRY_SIZE  : usize = 16;
+
+const SHT_SYMTAB : u32 = 2;
+const SHT_NOBITS : u32 = 8;
+const STT_FUNC   : u8 = 2;
+
+struct Section {
+    name   : String,
+    kind   : u32,
+    offset : usize,
+    size   : usize,
+    link   : usize
+}
+
+struct Symbol {
+    address : u64,
+    size    : u64,
+    name    : String
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: ksyms <kernel-elf>");
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(&path) {
+        eprintln!("ksyms: {}: {}", path, error);
+        process::exit(1);
+    }
+}
+
+fn run(path : &str) -> Result<(), String> {
+    let mut elf = fs::read(path).map_err(|e| e.to_string())?;
+    let sections = parse_sections(&elf)?;
+
+    let ksyms = sections.iter()
+        .find(|s| s.name == ".ksyms")
+        .ok_or("no .ksyms section, is the kernal crate linked in?")?;
+    if ksyms.kind == SHT_NOBITS {
+        return Err(".ksyms was emitted as NOBITS and has no space in the file".into());
+    }
+    if read(&elf, ksyms.offset, 4)? != KSYMS_MAGIC {
+        return Err(".ksyms does not start with the KSYM magic".into());
+    }
+
+    let symtab = sections.iter()
+        .find(|s| s.kind == SHT_SYMTAB)
+        .ok_or("no symbol table, was the kernel stripped?")?;
+    let strtab = sections.get(symtab.link).ok_or("symbol table has no string table")?;
+
+    let mut symbols = parse_functions(&elf, symtab, strtab)?;
+    symbols.sort_by_key(|s| s.address);
+    symbols.dedup_by_key(|s| s.address);
+
+    let table = build_table(&symbols)?;
+    if table.len() > ksyms.size {
+        return Err(format!(
+            "symbol table needs {} bytes but .ksyms is only {}, raise KSYMS_SIZE",
+            table.len(), ksyms.size
+        ));
+    }
+
+    elf[ksyms.offset..ksyms.offset + table.len()].copy_from_slice(&table);
+    fs::write(path, &elf).map_err(|e| e.to_string())?;
+
+    println!("ksyms: embedded {} symbols ({} of {} bytes)", symbols.len(), table.len(), ksyms.size);
+    Ok(())
+}
+
+fn parse_sections(elf : &[u8]) -> Result<Vec<Section>, String> {
+    if read(elf, 0, 4)? != b"\x7fELF" || read(elf, 4, 1)?[0] != 2 || read(elf, 5, 1)?[0] != 1 {
+        return Err("not a little endian ELF64 file".into());
+    }
+
+    let shoff = u64_at(elf, 0x28)? as usize;
+    let shentsize = u16_at(elf, 0x3A)? as usize;
+    let shnum = u16_at(elf, 0x3C)? as usize;
+    let shstrndx = u16_at(elf, 0x3E)? as usize;
+
+    let mut headers = Vec::with_capacity(shnum);
+    for i in 0..shnum {
+        let base = shoff + i * shentsize;
+        headers.push((
+            u32_at(elf, base)? as usize,
+            u32_at(elf, base + 4)?,
+            u64_at(elf, base + 24)? as usize,
+            u64_at(elf, base + 32)? as usize,
+            u32_at(elf, base + 40)? as usize
+        ));
+    }
+
+    let &(_, _, names_offset, _, _) = headers.get(shstrndx).ok_or("bad section name index")?;
+    headers.iter()
+        .map(|&(name, kind, offset, size, link)| {
+            Ok(Section { name : c_str_at(elf, names_offset + name)?, kind, offset, size, link })
+        })
+        .collect()
+}
+
+fn parse_functions(elf : &[u8], symtab : &Section, strtab : &Section) -> Result<Vec<Symbol>, String> {
+    let mut symbols = Vec::new();
+    for i in 0..(symtab.size / 24) {
+        let base = symtab.offset + i * 24;
+        let name = u32_at(elf, base)? as usize;
+        let info = read(elf, base + 4, 1)?[0];
+        let shndx = u16_at(elf, base + 6)?;
+        let address = u64_at(elf, base + 8)?;
+        let size = u64_at(elf, base + 16)?;
+
+        if info & 0xF != STT_FUNC || shndx == 0 || address == 0 {
+            continue;
+        }
+
+        let raw = c_str_at(elf, strtab.offset + name)?;
+        symbols.push(Symbol { address, size, name : demangle(&raw) });
+    }
+    Ok(symbols)
+}
+
+fn build_table(symbols : &[Symbol]) -> Result<Vec<u8>, String> {
+    let strings_offset = HEADER_SIZE + symbols.len() * ENTRY_SIZE;
+
+    let mut entries = Vec::with_capacity(symbols.len() * ENTRY_SIZE);
+    let mut strings = Vec::new();
+    for symbol in symbols {
+        entries.extend_from_slice(&symbol.address.to_le_bytes());
+        entries.extend_from_slice(&(symbol.size.min(u32::MAX as u64) as u32).to_le_bytes());
+        entries.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        strings.extend_from_slice(symbol.name.as_bytes());
+        strings.push(0);
+    }
+
+    let mut table = Vec::with_capacity(strings_offset + strings.len());
+    table.extend_from_slice(KSYMS_MAGIC);
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    table.extend_from_slice(&(strings_offset as u32).to_le_bytes());
+    table.extend_from_slice(&0u32.to_le_bytes());
+    table.extend_from_slice(&entries);
+    table.extend_from_slice(&strings);
+    Ok(table)
+}
+
+/// Demangles legacy Rust symbols (`_ZN...E`), dropping the trailing hash.
+/// Anything else is returned as is.
+fn demangle(symbol : &str) -> String {
+    let inner = match symbol.strip_prefix("_ZN").or_else(|| symbol.strip_prefix("__ZN")) {
+        Some(inner) => inner,
+        None => return symbol.to_string()
+    };
+    let inner = match inner.strip_suffix('E') {
+        Some(inner) => inner,
+        None => return symbol.to_string()
+    };
+
+    let mut parts = Vec::new();
+    let mut rest = inner;
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let len : usize = match rest[..digits].parse() {
+            Ok(len) if digits > 0 && digits + len <= rest.len() => len,
+            _ => return symbol.to_string()
+        };
+        parts.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+
+    if let Some(last) = parts.last() {
+        if last.len() == 17 && last.starts_with('h') && last[1..].bytes().all(|b| b.is_ascii_hexdigit()) {
+            parts.pop();
+        }
+    }
+
+    parts.iter().map(|p| unescape(p)).collect::<Vec<_>>().join("::")
+}
+
+fn unescape(part : &str) -> String {
+    let part = part.strip_prefix("_$").map(|p| format!("${}", p)).unwrap_or_else(|| part.to_string());
+
+    let mut out = String::new();
+    let mut rest = part.as_str();
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix("..") {
+            out.push_str("::");
+            rest = tail;
+            continue;
+        }
+        if rest.starts_with('$') {
+            if let Some(end) = rest[1..].find('$') {
+                let escape = &rest[1..end + 1];
+                let replacement = match escape {
+                    "SP" => Some('@'),
+                    "BP" => Some('*'),
+                    "RF" => Some('&'),
+                    "LT" => Some('<'),
+                    "GT" => Some('>'),
+                    "LP" => Some('('),
+                    "RP" => Some(')'),
+                    "C"  => Some(','),
+                    _ => escape.strip_prefix('u')
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .and_then(std::char::from_u32)
+                };
+                if let Some(c) = replacement {
+                    out.push(c);
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+        }
+        let c = rest.chars().next().unwrap();
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+fn read(elf : &[u8], offset : usize, len : usize) -> Result<&[u8], String> {
+    elf.get(offset..offset + len).ok_or_else(|| format!("truncated ELF at offset 0x{:x}", offset))
+}
+
+fn u16_at(elf : &[u8], offset : usize) -> Result<u16, String> {
+    let mut bytes = [0; 2];
+    bytes.copy_from_slice(read(elf, offset, 2)?);
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn u32_at(elf : &[u8], offset : usize) -> Result<u32, String> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(read(elf, offset, 4)?);
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn u64_at(elf : &[u8], offset : usize) -> Result<u64, String> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(read(elf, offset, 8)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn c_str_at(elf : &[u8], offset : usize) -> Result<String, String> {
+    let bytes = elf.get(offset..).ok_or("string table offset out of range")?;
+    let len = bytes.iter().position(|&b| b == 0).ok_or("unterminated string")?;
+    Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
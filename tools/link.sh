@@ -0,0 +1,35 @@
+#!/bin/sh
+# Linker for the kernel target: links with rust-lld as the target spec would,
+# then embeds the kernel symbol table into the output. Every kernel binary is
+# linked here, so `cargo run`, `cargo test` and `cargo bootimage` images all
+# carry their symbols.
+set -e
+
+ROOT="$(cd "$(dirname "$0")/.." && pwd)"
+KSYMS="$ROOT/target/tools/ksyms"
+
+# rustc passes `-flavor gnu` first and puts rust-lld on the PATH for us.
+rust-lld "$@"
+
+OUTPUT=""
+PREVIOUS=""
+for ARG in "$@"; do
+    if [ "$PREVIOUS" = "-o" ]; then
+        OUTPUT="$ARG"
+    fi
+    PREVIOUS="$ARG"
+done
+if [ -z "$OUTPUT" ]; then
+    echo "link.sh: no -o in the linker arguments" >&2
+    exit 1
+fi
+
+# Several kernel binaries may be linked at once, so build into a temporary
+# file and move it into place.
+if [ ! -x "$KSYMS" ] || [ "$ROOT/tools/ksyms.rs" -nt "$KSYMS" ]; then
+    mkdir -p "$ROOT/target/tools"
+    rustc --edition 2018 -O "$ROOT/tools/ksyms.rs" -o "$KSYMS.$$"
+    mv -f "$KSYMS.$$" "$KSYMS"
+fi
+
+"$KSYMS" "$OUTPUT"
@@ -0,0 +1,4 @@
+#!/bin/sh
+# Cargo runner: hands over to bootimage to build the disk image and boot it.
+# The symbol table is already in the kernel, `tools/link.sh` embeds it.
+exec bootimage runner "$@"